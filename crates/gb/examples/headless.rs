@@ -6,14 +6,24 @@ use gb_shared::CPU_FREQ;
 fn main() {
     let args = std::env::args().collect::<Vec<String>>();
     let rom_path = args.get(1).unwrap();
-    let mut cpu_seconds = args.get(2).unwrap().parse::<u32>().unwrap();
+    let seconds = args.get(2).unwrap().parse::<u32>().unwrap();
+    let mut cpu_seconds = seconds;
 
     let rom = std::fs::read(std::path::Path::new(rom_path)).unwrap();
     let cart = Cartridge::try_from(rom).unwrap();
-    let mut gb = GameBoy::new(Manifest { cart, sample_rate: Some(44_1000) });
+    let mut gb = GameBoy::new(Manifest::new(cart).with_sample_rate(44_1000));
 
+    let start = std::time::Instant::now();
     while cpu_seconds > 0 {
         gb.continue_clocks((cpu_seconds.min(512)) * CPU_FREQ);
         cpu_seconds = cpu_seconds.saturating_sub(512);
     }
+
+    let elapsed = start.elapsed();
+    println!(
+        "Emulated {}s in {:.2}s, {:.1}x real-time",
+        seconds,
+        elapsed.as_secs_f64(),
+        seconds as f64 / elapsed.as_secs_f64()
+    );
 }
@@ -0,0 +1,69 @@
+use gb_shared::MachineModel;
+
+const DMG_BOOT_ROM_SIZE: usize = 0x100;
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// Boot ROM of DMG, MGB, SGB(256 bytes) or CGB(2304 bytes).
+///
+/// It's mapped over [0x0000, 0x00FF], and also [0x0200, 0x08FF] for CGB,
+/// until 0xFF50 is written.
+///
+/// @see https://gbdev.io/pandocs/Power_Up_Sequence.html
+#[derive(Clone)]
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl TryFrom<Vec<u8>> for BootRom {
+    type Error = anyhow::Error;
+
+    fn try_from(data: Vec<u8>) -> Result<Self, Self::Error> {
+        match data.len() {
+            DMG_BOOT_ROM_SIZE | CGB_BOOT_ROM_SIZE => Ok(Self { data }),
+            len => anyhow::bail!("Invalid boot ROM size {:#X}", len),
+        }
+    }
+}
+
+impl BootRom {
+    pub fn machine_model(&self) -> MachineModel {
+        if self.data.len() == CGB_BOOT_ROM_SIZE {
+            MachineModel::CGB
+        } else {
+            MachineModel::DMG
+        }
+    }
+
+    /// Read `addr` if it's mapped to boot ROM.
+    #[inline]
+    pub(crate) fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x00FF => Some(self.data[addr as usize]),
+            0x0200..=0x08FF => self.data.get(addr as usize).copied(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_regions() {
+        let dmg = BootRom::try_from(vec![0x12; DMG_BOOT_ROM_SIZE]).unwrap();
+        assert_eq!(dmg.machine_model(), MachineModel::DMG);
+        assert_eq!(dmg.read(0x00FF), Some(0x12));
+        assert_eq!(dmg.read(0x0100), None);
+        assert_eq!(dmg.read(0x0200), None);
+
+        let cgb = BootRom::try_from(vec![0x34; CGB_BOOT_ROM_SIZE]).unwrap();
+        assert_eq!(cgb.machine_model(), MachineModel::CGB);
+        assert_eq!(cgb.read(0x0000), Some(0x34));
+        assert_eq!(cgb.read(0x0150), None);
+        assert_eq!(cgb.read(0x08FF), Some(0x34));
+        assert_eq!(cgb.read(0x0900), None);
+
+        assert!(BootRom::try_from(vec![0; 0x200]).is_err());
+    }
+}
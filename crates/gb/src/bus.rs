@@ -1,20 +1,25 @@
 use gb_apu::{Apu, ApuSnapshot};
 use gb_cartridge::Cartridge;
 use gb_ppu::{Ppu, PpuSnapshot};
-use gb_shared::{command::Command, Memory, Snapshot};
+use gb_shared::{command::Command, is_bit_set, HardwareModel, MachineModel, Memory, Snapshot};
 use std::ops::{Deref, DerefMut};
 
 use crate::{
+    boot_rom::BootRom,
+    debugger::{Access, Debugger},
     dma::{DmaSnapshot, DMA},
     hram::{HighRam, HighRamSnapshot},
     joypad::Joypad,
     misc_ram::{MiscRam, MiscRamSnapshot},
-    serial::{Serial, SerialSnapshot},
+    page_table::{Page, PageTable},
+    scheduler::{Run, Scheduler},
+    serial::{Serial, SerialLink, SerialSnapshot},
     timer::{Timer, TimerSnapshot},
     vdma::{Vdma, VdmaSnapshot},
     wram::{WorkRam, WorkRamSnapshot},
 };
 
+#[derive(Clone)]
 pub(crate) struct BusInner {
     /// R/W. Set the bit to be 1 if the corresponding
     /// interrupt is enabled. Lower bits have higher
@@ -43,20 +48,68 @@ pub(crate) struct BusInner {
     vdma: Vdma,
     mram: MiscRam,
     /// Serial transfer
-    serial: Serial,
+    pub(crate) serial: Serial,
     joypad: Joypad,
     timer: Timer,
     clocks: u8,
+    scheduler: Scheduler,
+    /// KEY1 bit 7, CPU is running in double speed mode(CGB only).
+    double_speed: bool,
+    /// KEY1 bit 0, armed by software and consumed by STOP instruction(CGB only).
+    /// @see https://gbdev.io/pandocs/CGB_Registers.html#ff4d--key1-cgb-mode-only-prepare-speed-switch
+    prepare_speed_switch: bool,
+    machine_model: MachineModel,
+    pub(crate) model: HardwareModel,
+    boot_rom: Option<BootRom>,
+    /// Boot ROM is mapped until 0xFF50 is written.
+    boot_rom_mapped: bool,
+    /// KEY0, written by CGB boot ROM to select CGB mode or DMG compatibility mode.
+    /// @see https://gbdev.io/pandocs/CGB_Registers.html#ff4c--key0sys-cgb-mode-only-cpu-mode-select
+    key0: u8,
+    /// RP, infrared communications port(CGB only).
+    rp: u8,
+    /// [0xFEA0, 0xFEFF]
+    prohibited_ram: Vec<u8>,
     pub(crate) ppu: Ppu,
     pub(crate) apu: Apu,
+    pub(crate) debugger: Debugger,
+    pages: PageTable,
 }
 
 impl Memory for BusInner {
+    #[inline]
     fn write(&mut self, addr: u16, value: u8) {
+        let low = addr as usize & 0xFF;
+        match self.pages.get(addr) {
+            Page::Vram(base) => self.ppu.vram_mut()[base as usize + low] = value,
+            Page::Sram(bank, base) => {
+                self.cart.ram_bank_data_mut(bank as usize)[base as usize + low] = value
+            }
+            Page::Wram(base) => self.wram.ram_mut()[base as usize + low] = value,
+            Page::Rom(_) | Page::Slow => self.write_slow(addr, value),
+        }
+    }
+
+    #[inline]
+    fn read(&self, addr: u16) -> u8 {
+        let low = addr as usize & 0xFF;
+        match self.pages.get(addr) {
+            Page::Rom(base) => self.cart.rom()[base as usize + low],
+            Page::Vram(base) => self.ppu.vram()[base as usize + low],
+            Page::Sram(bank, base) => self.cart.ram_bank_data(bank as usize)[base as usize + low],
+            Page::Wram(base) => self.wram.ram()[base as usize + low],
+            Page::Slow => self.read_slow(addr),
+        }
+    }
+}
+
+impl BusInner {
+    fn write_slow(&mut self, addr: u16, value: u8) {
         match addr {
             0x0000..=0x7FFF => {
                 // ROM data
                 self.cart.write(addr, value);
+                self.remap();
             }
             0x8000..=0x9FFF => {
                 // VRAM
@@ -65,25 +118,44 @@ impl Memory for BusInner {
             0xA000..=0xBFFF => {
                 // EXT-RAM, from cartridge
                 self.cart.write(addr, value);
+                self.remap();
             }
             0xC000..=0xDFFF => {
                 // WRAM
                 self.wram.write(addr, value);
             }
-            0xE000..=0xFDFF => log::warn!("Unusable ECHO RAM [0xE000, 0xFDFF]"),
+            0xE000..=0xFDFF => {
+                // ECHO RAM, mirror of [0xC000, 0xDDFF]
+                self.wram.write(addr - 0x2000, value);
+            }
             0xFE00..=0xFE9F => {
                 if self.dma.active() {
                     return;
                 }
+                if self.model.has_oam_bug() {
+                    self.ppu.corrupt_oam();
+                }
                 // OAM
                 self.ppu.write(addr, value);
             }
-            0xFEA0..=0xFEFF => log::warn!("Unusable memory [0xFEA0, 0xFEFF]"),
+            0xFEA0..=0xFEFF => {
+                if self.model.has_oam_bug() {
+                    self.ppu.corrupt_oam();
+                }
+                if self.has_prohibited_ram() && self.oam_accessible() {
+                    self.prohibited_ram[addr as usize - 0xFEA0] = value;
+                }
+            }
             0xFF00..=0xFF7F => {
                 match addr {
                     0xFF00 => self.joypad.write(addr, value),
                     0xFF01..=0xFF02 => self.serial.write(addr, value),
-                    0xFF04..=0xFF07 => self.timer.write(addr, value),
+                    0xFF04..=0xFF07 => {
+                        // Resetting DIV may clock the frame sequencer.
+                        let div_apu = self.div_apu_bit();
+                        self.timer.write(addr, value);
+                        self.detect_div_apu_edge(div_apu);
+                    }
                     0xFF0F => {
                         // IF
                         self.interrupt_flag = 0xE0 | value
@@ -99,11 +171,36 @@ impl Memory for BusInner {
                     0xFF40..=0xFF4B => {
                         self.ppu.write(addr, value);
                     }
+                    0xFF4C => {
+                        // KEY0, locked after boot.
+                        if self.machine_model == MachineModel::CGB && self.boot_rom_mapped {
+                            self.key0 = value;
+                        }
+                    }
                     0xFF4D => {
-                        log::error!("Switch speed is not supported yet, {}", value);
+                        // KEY1
+                        if self.machine_model == MachineModel::CGB {
+                            self.prepare_speed_switch = is_bit_set!(value, 0);
+                        }
                     }
+                    0xFF50 => {
+                        // Unmap boot ROM, which can't be mapped again.
+                        if self.boot_rom_mapped && is_bit_set!(value, 0) {
+                            self.boot_rom_mapped = false;
+                            if self.machine_model == MachineModel::CGB && self.key0 & 0x0C == 0x04 {
+                                self.enter_dmg_compatibility_mode();
+                            }
+                            self.remap();
+                        }
+                    }
+                    // CGB registers, locked in DMG mode.
+                    0xFF4F | 0xFF51..=0xFF55 | 0xFF70
+                        if self.machine_model != MachineModel::CGB => {}
                     // VRAM bank(VBK)
-                    0xFF4F => self.ppu.write(addr, value),
+                    0xFF4F => {
+                        self.ppu.write(addr, value);
+                        self.remap();
+                    }
                     0xFF51..=0xFF54 => self.vdma.write(addr, value),
                     0xFF55 => {
                         // @see https://gbdev.io/pandocs/CGB_Registers.html#documented-registers:~:text=hblank%20dma%20should%20not%20be%20started%20(write%20to%20ff55)%20during%20a%20hblank%20period
@@ -114,17 +211,27 @@ impl Memory for BusInner {
                         self.vdma.write(addr, value);
                     }
                     0xFF56 => {
-                        log::warn!("RP is not supported yet");
+                        // RP, infrared is not emulated.
+                        if self.machine_model == MachineModel::CGB {
+                            self.rp = value & 0xC1;
+                        }
                     }
                     // BCPS, BCPD, OCPS, OCPD
                     0xFF68..=0xFF6B => self.ppu.write(addr, value),
-                    // OPRI
-                    0xFF6C => self.ppu.write(addr, value),
+                    0xFF6C => {
+                        // OPRI, locked after boot.
+                        if self.machine_model == MachineModel::CGB && self.boot_rom_mapped {
+                            self.ppu.write(addr, value);
+                        }
+                    }
                     // WRAM bank(SVBK)
-                    0xFF70 => self.wram.write(addr, value),
-                    _ => {
-                        log::error!("Unsupported bus write {:#X} {:#X}", addr, value);
+                    0xFF70 => {
+                        self.wram.write(addr, value);
+                        self.remap();
                     }
+                    0xFF72..=0xFF75 => self.mram.write(addr, value),
+                    // Unmapped
+                    _ => {}
                 }
             }
             0xFF80..=0xFFFE => {
@@ -138,9 +245,14 @@ impl Memory for BusInner {
         }
     }
 
-    fn read(&self, addr: u16) -> u8 {
+    fn read_slow(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x7FFF => {
+                if self.boot_rom_mapped {
+                    if let Some(value) = self.boot_rom.as_ref().and_then(|rom| rom.read(addr)) {
+                        return value;
+                    }
+                }
                 // ROM data
                 self.cart.read(addr)
             }
@@ -157,8 +269,8 @@ impl Memory for BusInner {
                 self.wram.read(addr)
             }
             0xE000..=0xFDFF => {
-                log::warn!("Unusable ECHO RAM [0xE000, 0xFDFF]");
-                0
+                // ECHO RAM, mirror of [0xC000, 0xDDFF]
+                self.wram.read(addr - 0x2000)
             }
             0xFE00..=0xFE9F => {
                 if self.dma.active() {
@@ -169,8 +281,17 @@ impl Memory for BusInner {
                 self.ppu.read(addr)
             }
             0xFEA0..=0xFEFF => {
-                log::warn!("Unusable memory [0xFEA0, 0xFEFF]");
-                0
+                if !self.oam_accessible() {
+                    0xFF
+                } else if self.has_prohibited_ram() {
+                    self.prohibited_ram[addr as usize - 0xFEA0]
+                } else if self.model.machine_model() == MachineModel::CGB {
+                    // The high nibble of the lower address byte twice.
+                    let nibble = addr as u8 & 0xF0;
+                    nibble | (nibble >> 4)
+                } else {
+                    0x00
+                }
             }
             0xFF00..=0xFF7F => {
                 match addr {
@@ -185,24 +306,55 @@ impl Memory for BusInner {
                     0xFF46 => self.dma.read(addr),
                     // Exclude 0xFF46(DMA)
                     0xFF40..=0xFF4B => self.ppu.read(addr),
-                    // TODO: Switch speed.
-                    0xFF4D => 0x00,
+                    0xFF4D => {
+                        // KEY1
+                        if self.machine_model == MachineModel::CGB {
+                            0x7E | ((self.double_speed as u8) << 7)
+                                | (self.prepare_speed_switch as u8)
+                        } else {
+                            0xFF
+                        }
+                    }
+                    0xFF4C => {
+                        // KEY0
+                        if self.machine_model == MachineModel::CGB && self.boot_rom_mapped {
+                            self.key0
+                        } else {
+                            0xFF
+                        }
+                    }
+                    0xFF4F | 0xFF51..=0xFF55 | 0xFF70
+                        if self.machine_model != MachineModel::CGB =>
+                    {
+                        0xFF
+                    }
                     // VRAM bank(VBK)
                     0xFF4F => self.ppu.read(addr),
+                    0xFF50 => 0xFF,
                     0xFF51..=0xFF55 => self.vdma.read(addr),
                     0xFF56 => {
-                        log::warn!("RP is not supported yet");
-                        0
+                        // RP, no light is received.
+                        if self.machine_model == MachineModel::CGB {
+                            self.rp | 0x3E
+                        } else {
+                            0xFF
+                        }
                     }
                     // BCPS, BCPD, OCPS, OCPD
                     0xFF68..=0xFF6B => self.ppu.read(addr),
-                    0xFF6C => self.ppu.read(addr),
+                    0xFF6C => {
+                        // OPRI
+                        if self.model.machine_model() == MachineModel::CGB {
+                            self.ppu.read(addr)
+                        } else {
+                            0xFF
+                        }
+                    }
                     // WRAM bank(SVBK)
                     0xFF70 => self.wram.read(addr),
-                    _ => {
-                        log::error!("Unsupported bus read {:#X}", addr);
-                        0
-                    }
+                    0xFF72..=0xFF75 => self.mram.read(addr),
+                    // Unmapped
+                    _ => 0xFF,
                 }
             }
             0xFF80..=0xFFFE => {
@@ -217,61 +369,308 @@ impl Memory for BusInner {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct Bus {
-    inner: std::rc::Rc<std::cell::UnsafeCell<BusInner>>,
+    inner: Box<BusInner>,
+}
+
+impl BusInner {
+    /// [0xFEA0, 0xFEFF] is RAM on CGB revisions before E, though it's
+    /// masked with a revision specific value, which is not emulated.
+    /// @see https://gbdev.io/pandocs/Memory_Map.html#fea0feff-range
+    #[inline]
+    fn has_prohibited_ram(&self) -> bool {
+        matches!(
+            self.model,
+            HardwareModel::CGB0
+                | HardwareModel::CGBA
+                | HardwareModel::CGBB
+                | HardwareModel::CGBC
+                | HardwareModel::CGBD
+        )
+    }
+
+    /// The bit of DIV clocking the APU frame sequencer on its falling edge.
+    /// @see https://gbdev.io/pandocs/Audio_details.html#div-apu
+    #[inline]
+    fn div_apu_bit(&self) -> bool {
+        let bit = if self.double_speed { 13 } else { 12 };
+        is_bit_set!(self.timer.div(), bit)
+    }
+
+    #[inline]
+    fn detect_div_apu_edge(&mut self, div_apu: bool) {
+        if div_apu && !self.div_apu_bit() {
+            self.apu.clock_frame_sequencer();
+        }
+    }
+
+    /// Map memory which can be accessed directly, whenever banks may be
+    /// switched.
+    fn remap(&mut self) {
+        let pages = &mut self.pages;
+
+        let rom_len = self.cart.rom().len();
+        let rom_bank = self.cart.rom_bank() * 0x4000;
+        if self.boot_rom_mapped {
+            // Boot ROM overlays the cartridge header.
+            pages.unmap(0x0000..=0x08FF);
+            pages.map(0x0900..=0x3FFF, |offset| Page::Rom(0x0900 + offset as u32));
+        } else {
+            pages.map(0x0000..=0x3FFF, |offset| Page::Rom(offset as u32));
+        }
+        if rom_bank + 0x4000 <= rom_len {
+            pages.map(0x4000..=0x7FFF, |offset| Page::Rom((rom_bank + offset) as u32));
+        } else {
+            pages.unmap(0x4000..=0x7FFF);
+        }
+
+        let vram_bank = self.ppu.vram_bank_offset();
+        pages.map(0x8000..=0x9FFF, |offset| Page::Vram((vram_bank + offset) as u32));
+
+        match self.cart.ram_bank() {
+            Some(bank) => {
+                pages.map(0xA000..=0xBFFF, |offset| Page::Sram(bank as u16, offset as u16))
+            }
+            None => pages.unmap(0xA000..=0xBFFF),
+        }
+
+        // WRAM, and ECHO RAM mirroring it.
+        let wram_bank = self.wram.bank_offset();
+        for start in [0xC000, 0xE000] {
+            pages.map(start..=start + 0x0FFF, |offset| Page::Wram(offset as u32));
+        }
+        pages.map(0xD000..=0xDFFF, |offset| Page::Wram((wram_bank + offset) as u32));
+        pages.map(0xF000..=0xFDFF, |offset| Page::Wram((wram_bank + offset) as u32));
+    }
+
+    /// Replace the link of the serial port, which may start or stop the
+    /// transfer waiting for it.
+    pub(crate) fn replace_serial_link(
+        &mut self,
+        link: Option<Box<dyn SerialLink>>,
+    ) -> Option<Box<dyn SerialLink>> {
+        self.run_pending();
+        let prev = std::mem::replace(&mut self.serial.link, link);
+        self.reschedule();
+        prev
+    }
+
+    /// Run components up to now.
+    pub(crate) fn run_pending(&mut self) {
+        while let Some(run) = self.scheduler.next() {
+            match run {
+                Run::Idle(m_cycles) => self.step_idle(m_cycles),
+                Run::Busy => {
+                    self.step_m_cycle();
+                    self.reschedule();
+                }
+            }
+        }
+    }
+
+    /// Set idle machine cycles, counted from where components have run to,
+    /// in which no interrupt can be requested and components run
+    /// independently of each other.
+    fn reschedule(&mut self) {
+        let idle = self.idle_cycles_until_event();
+        self.scheduler.reschedule(idle);
+    }
+
+    fn idle_cycles_until_event(&self) -> u32 {
+        if self.dma.in_progress() || self.vdma.in_progress() || self.serial.polling() {
+            return 0;
+        }
+
+        let dots = if self.double_speed { 2 } else { 4 };
+        let div_apu_bit = if self.double_speed { 13 } else { 12 };
+        let ppu = self.ppu.idle_dots() / dots;
+        let timer =
+            self.timer.idle_clocks().min(self.timer.clocks_until_falling_edge(div_apu_bit, 1) - 1)
+                / 4;
+        // A transfer with external clock and no link has no event.
+        let serial = match self.serial.shift_bit() {
+            Some(bit) => (self.timer.clocks_until_falling_edge(bit, 1) - 1) / 4,
+            None => u32::MAX,
+        };
+
+        ppu.min(timer).min(serial)
+    }
+
+    /// Run idle machine cycles, see [`BusInner::reschedule`].
+    fn step_idle(&mut self, m_cycles: u32) {
+        let dots = m_cycles * if self.double_speed { 2 } else { 4 };
+        self.ppu.step_idle(dots);
+        self.apu.step(dots);
+        self.timer.step_idle(m_cycles * 4);
+    }
+
+    fn step_m_cycle(&mut self) {
+        // PPU and APU keep running at real-time rate, which is
+        // half of CPU's in double speed mode.
+        let dots = if self.double_speed { 2 } else { 4 };
+
+        for _ in 0..dots {
+            self.ppu.step();
+            let irq = self.ppu.take_irq();
+            self.request_interrupt(irq);
+        }
+        self.apu.step(dots);
+
+        for _ in 0..4 {
+            self.step_timer();
+            let irq = self.timer.take_irq();
+            self.request_interrupt(irq);
+
+            let div = self.timer.div();
+            self.serial.step(div);
+            let irq = self.serial.take_irq();
+            self.request_interrupt(irq);
+        }
+
+        // It costs 160 machine cycles to transfer 160 bytes of data.
+        // https://gbdev.io/pandocs/OAM_DMA_Transfer.html#ff46--dma-oam-dma-source-address--start:~:text=the%20transfer%20takes%20160%20machine%20cycles
+        self.step_dma();
+    }
+
+    fn step_dma(&mut self) {
+        if let Some((src, dst)) = self.dma.next_addr() {
+            let value = self.read(src);
+            self.ppu.write(dst, value)
+        }
+    }
+
+    fn step_timer(&mut self) {
+        let div_apu = self.div_apu_bit();
+        self.timer.step();
+        self.detect_div_apu_edge(div_apu);
+    }
+
+    fn request_interrupt(&mut self, irq: u8) {
+        self.interrupt_flag |= irq;
+    }
+
+    #[inline]
+    fn oam_accessible(&self) -> bool {
+        !self.dma.active() && self.ppu.oam_accessible()
+    }
+
+    fn memory_bus(&self, addr: u16) -> Option<MemoryBus> {
+        match addr {
+            0x8000..=0x9FFF => Some(MemoryBus::Video),
+            0xC000..=0xFDFF if self.model.machine_model() == MachineModel::CGB => {
+                Some(MemoryBus::Work)
+            }
+            0x0000..=0x7FFF | 0xA000..=0xFDFF => Some(MemoryBus::External),
+            _ => None,
+        }
+    }
+
+    /// During OAM DMA, the CPU accessing the bus DMA is reading from
+    /// sees the byte DMA is reading, and its writes are ignored.
+    /// Returns the address DMA is reading from on conflict.
+    /// @see https://gbdev.io/pandocs/OAM_DMA_Transfer.html#oam-dma-bus-conflicts
+    fn dma_conflict(&self, addr: u16) -> Option<u16> {
+        let src = self.dma.current_src()?;
+        let bus = self.memory_bus(addr)?;
+        (self.memory_bus(src) == Some(bus)).then_some(src)
+    }
+
+    /// Lock CGB registers, as CGB runs a DMG cartridge.
+    /// @see https://gbdev.io/pandocs/CGB_Registers.html#ff4c--key0sys-cgb-mode-only-cpu-mode-select
+    fn enter_dmg_compatibility_mode(&mut self) {
+        self.machine_model = MachineModel::DMG;
+        self.serial.machine_model = MachineModel::DMG;
+        self.mram.cgb_mode = false;
+        self.ppu.enter_dmg_compatibility_mode();
+    }
 }
 
 impl Deref for Bus {
     type Target = BusInner;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.inner.get() }
+        &self.inner
     }
 }
 
 impl DerefMut for Bus {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.inner.get() }
+        &mut self.inner
     }
 }
 
 impl Bus {
-    pub(crate) fn new(cart: Cartridge, sample_rate: Option<u32>) -> Self {
-        let machine_model = cart.machine_model();
-        let compatibility_palette_id = cart.compatibility_palette_id();
-        Self {
-            inner: std::rc::Rc::new(std::cell::UnsafeCell::new(BusInner {
+    /// `machine_model` is the mode `model` runs in after boot, e.g. CGB
+    /// runs DMG cartridges in DMG compatibility mode.
+    pub(crate) fn new(
+        cart: Cartridge,
+        model: HardwareModel,
+        machine_model: MachineModel,
+        boot_rom: Option<BootRom>,
+        sample_rate: Option<u32>,
+    ) -> Self {
+        let cgb_hardware = model.machine_model() == MachineModel::CGB;
+        let boot_rom_mapped = boot_rom.is_some();
+        // CGB boot ROM runs in CGB mode, and it enters DMG compatibility
+        // mode for DMG cartridges.
+        let boot_machine_model =
+            if boot_rom_mapped && cgb_hardware { MachineModel::CGB } else { machine_model };
+        let dmg_compatibility = cgb_hardware && boot_machine_model == MachineModel::DMG;
+
+        let compatibility_palette_id = match machine_model {
+            MachineModel::DMG => cart.compatibility_palette_id(),
+            MachineModel::CGB => None,
+        };
+        let ppu = if dmg_compatibility {
+            // Do what the boot ROM does.
+            let mut ppu = Ppu::new(MachineModel::CGB, compatibility_palette_id);
+            ppu.load_compatibility_palette(compatibility_palette_id);
+            ppu.enter_dmg_compatibility_mode();
+            ppu
+        } else {
+            Ppu::new(boot_machine_model, compatibility_palette_id)
+        };
+
+        let mut bus = Self {
+            inner: Box::new(BusInner {
                 cart,
-                wram: WorkRam::new(machine_model),
-                hram: HighRam::new(),
+                wram: WorkRam::new(model, model.machine_model()),
+                hram: HighRam::new(model),
                 interrupt_enable: 0,
                 interrupt_flag: 0xE0,
                 dma: DMA::new(),
-                serial: Serial::new(),
+                serial: Serial::new(boot_machine_model),
                 joypad: Joypad::new(),
-                timer: Timer::new(),
-                ppu: Ppu::new(machine_model, compatibility_palette_id),
-                apu: Apu::new(sample_rate),
+                timer: Timer::new(model),
+                ppu,
+                apu: Apu::new(model.machine_model(), sample_rate),
                 vdma: Vdma::new(),
-                mram: MiscRam::new(machine_model),
+                mram: MiscRam::new(model, boot_machine_model),
                 clocks: 0,
-            })),
-        }
-    }
+                scheduler: Scheduler::default(),
+                debugger: Debugger::default(),
+                double_speed: false,
+                prepare_speed_switch: false,
+                machine_model: boot_machine_model,
+                model,
+                boot_rom,
+                boot_rom_mapped,
+                key0: if dmg_compatibility { 0x04 } else { 0x00 },
+                rp: 0,
+                prohibited_ram: vec![0; 0x60],
+                pages: PageTable::default(),
+            }),
+        };
+        bus.remap();
 
-    fn step_dma(&mut self) {
-        if let Some((src, dst)) = self.dma.next_addr() {
-            let value = self.read(src);
-            self.ppu.write(dst, value)
+        if boot_rom_mapped {
+            // Power-on state, the boot ROM takes care of the rest.
+            bus.timer.write(0xFF04, 0);
+            bus.ppu.power_on();
         }
-    }
-
-    fn request_interrupt(&mut self, irq: u8) {
-        self.write(0xFF0F, self.read(0xFF0F) | irq);
-    }
 
-    pub(crate) fn step_timer(&mut self) {
-        self.timer.step();
+        bus
     }
 
     pub(crate) fn exec_command(&mut self, command: Command) {
@@ -289,22 +688,33 @@ impl gb_shared::Bus for Bus {
         self.clocks = clocks % 4;
         debug_assert!(m_cycles > 0);
 
-        for _ in 0..m_cycles {
-            for _ in 0..4 {
-                self.ppu.step();
-                let irq = self.ppu.take_irq();
-                self.request_interrupt(irq);
+        if self.scheduler.step(m_cycles as u32) {
+            self.run_pending();
+        }
+    }
 
-                self.step_timer();
-                let irq = self.timer.take_irq();
-                self.request_interrupt(irq);
+    fn idle_cycles(&self) -> u32 {
+        self.scheduler.idle_cycles()
+    }
 
-                self.apu.step();
-            }
+    fn peek(&self, addr: u16) -> u8 {
+        self.deref().read(addr)
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        if observable(addr) {
+            self.run_pending();
+        }
+        if (0xFF10..=0xFF3F).contains(&addr) {
+            self.apu.sync();
+        }
+        <Self as Memory>::read(self, addr)
+    }
 
-            // It costs 160 machine cycles to transfer 160 bytes of data.
-            // https://gbdev.io/pandocs/OAM_DMA_Transfer.html#ff46--dma-oam-dma-source-address--start:~:text=the%20transfer%20takes%20160%20machine%20cycles
-            self.step_dma();
+    fn idu_access(&mut self, addr: u16) {
+        if (0xFE00..=0xFEFF).contains(&addr) && self.model.has_oam_bug() {
+            self.run_pending();
+            self.ppu.corrupt_oam();
         }
     }
 
@@ -315,6 +725,7 @@ impl gb_shared::Bus for Bus {
     }
 
     fn step_vdma(&mut self) {
+        self.run_pending();
         let ly = self.ppu.ly();
         let hblank = self.ppu.lcd_mode().hblank();
         if !self.vdma.active(ly, hblank) {
@@ -322,27 +733,67 @@ impl gb_shared::Bus for Bus {
         }
 
         if let Some((src_addr, dst_addr)) = self.vdma.step(ly, hblank) {
-            let value = self.read(src_addr);
+            let value = self.deref().read(src_addr);
             self.ppu.write(dst_addr, value);
         }
     }
-}
 
-impl Clone for Bus {
-    fn clone(&self) -> Self {
-        Self { inner: self.inner.clone() }
+    fn set_double_speed(&mut self, double_speed: bool) {
+        self.run_pending();
+        self.double_speed = double_speed;
+        self.prepare_speed_switch = false;
+        self.reschedule();
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x4000..=0x7FFF => self.cart.rom_bank(),
+            _ => 0,
+        }
     }
 }
 
+/// Buses the CPU shares with OAM DMA.
+#[derive(Debug, PartialEq, Eq)]
+enum MemoryBus {
+    External,
+    Video,
+    /// WRAM has its own bus on CGB.
+    Work,
+}
+
+/// Whether accessing `addr` observes states of components, which must
+/// be run up to now before that. IF is excluded, as no interrupt is
+/// requested in idle cycles.
+#[inline]
+fn observable(addr: u16) -> bool {
+    matches!(addr, 0x8000..=0x9FFF | 0xFE00..=0xFF0E | 0xFF10..=0xFF7F)
+}
+
+/// Memory accesses from CPU.
 impl Memory for Bus {
     #[inline]
     fn write(&mut self, addr: u16, value: u8) {
-        self.deref_mut().write(addr, value);
+        self.debugger.on_access(addr, value, Access::Write);
+        let observable = observable(addr);
+        if observable {
+            self.run_pending();
+        }
+        if self.dma_conflict(addr).is_none() {
+            self.deref_mut().write(addr, value);
+        }
+        if observable {
+            // The write may bring next event forward, e.g. enabling timer.
+            self.reschedule();
+        }
     }
 
+    /// Components may lag behind, see [`gb_shared::Bus::cpu_read`].
     #[inline]
     fn read(&self, addr: u16) -> u8 {
-        self.deref().read(addr)
+        let value = self.deref().read(self.dma_conflict(addr).unwrap_or(addr));
+        self.debugger.on_access(addr, value, Access::Read);
+        value
     }
 }
 
@@ -359,8 +810,15 @@ pub(crate) struct BusSnapshot {
     serial: SerialSnapshot,
     timer: TimerSnapshot,
     clocks: u8,
+    double_speed: bool,
+    prepare_speed_switch: bool,
+    boot_rom_mapped: bool,
+    key0: u8,
+    rp: u8,
+    prohibited_ram: Vec<u8>,
     ppu: PpuSnapshot,
     apu: ApuSnapshot,
+    scheduler: Scheduler,
 }
 
 impl Snapshot for Bus {
@@ -379,8 +837,15 @@ impl Snapshot for Bus {
             serial: self.serial.take_snapshot(),
             timer: self.timer.take_snapshot(),
             clocks: self.clocks,
+            double_speed: self.double_speed,
+            prepare_speed_switch: self.prepare_speed_switch,
+            boot_rom_mapped: self.boot_rom_mapped,
+            key0: self.key0,
+            rp: self.rp,
+            prohibited_ram: self.prohibited_ram.clone(),
             ppu: self.ppu.take_snapshot(),
             apu: self.apu.take_snapshot(),
+            scheduler: self.scheduler.clone(),
         }
     }
 
@@ -396,7 +861,218 @@ impl Snapshot for Bus {
         self.serial.restore_snapshot(snapshot.serial);
         self.timer.restore_snapshot(snapshot.timer);
         self.clocks = snapshot.clocks;
+        self.double_speed = snapshot.double_speed;
+        self.prepare_speed_switch = snapshot.prepare_speed_switch;
+        self.boot_rom_mapped = snapshot.boot_rom_mapped;
+        self.key0 = snapshot.key0;
+        self.rp = snapshot.rp;
+        self.prohibited_ram = snapshot.prohibited_ram;
         self.ppu.restore_snapshot(snapshot.ppu);
         self.apu.restore_snapshot(snapshot.apu);
+        self.scheduler = snapshot.scheduler;
+        self.remap();
+        if self.machine_model == MachineModel::CGB
+            && !self.boot_rom_mapped
+            && self.key0 & 0x0C == 0x04
+        {
+            self.enter_dmg_compatibility_mode();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gb_shared::Bus as _;
+
+    /// Build a cartridge of `rom_banks` 16KiB banks, whose header can be
+    /// customized before checksum is calculated.
+    fn build_cart(rom_banks: usize, customize: impl FnOnce(&mut [u8])) -> Cartridge {
+        let mut rom = vec![0; 0x4000 * rom_banks];
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        customize(&mut rom);
+        rom[0x014D] = rom[0x0134..0x014D]
+            .iter()
+            .fold(0u8, |checksum, v| checksum.wrapping_sub(v.wrapping_add(1)));
+
+        Cartridge::try_from(rom).unwrap()
+    }
+
+    fn new_bus(cart: Cartridge, model: HardwareModel) -> Bus {
+        let machine_model = match model.machine_model() {
+            MachineModel::DMG => MachineModel::DMG,
+            MachineModel::CGB => cart.machine_model(),
+        };
+        Bus::new(cart, model, machine_model, None, None)
+    }
+
+    /// Wait for VBlank and turn LCD off, so that OAM is always accessible.
+    fn turn_lcd_off(bus: &mut Bus) {
+        while bus.cpu_read(0xFF44) != 0x90 {
+            bus.step(4);
+        }
+        bus.write(0xFF40, 0x00);
+    }
+
+    #[test]
+    fn echo_ram_prohibited_area_and_unmapped_io() {
+        for (model, prohibited) in [
+            (HardwareModel::DMG, 0x00),
+            (HardwareModel::SGB, 0x00),
+            (HardwareModel::CGBC, 0x5A),
+            (HardwareModel::CGBE, 0xBB),
+            (HardwareModel::AGB, 0xBB),
+        ] {
+            let mut bus = new_bus(build_cart(2, |_| {}), model);
+            turn_lcd_off(&mut bus);
+
+            bus.write(0xC123, 0x42);
+            bus.write(0xF000, 0x99);
+            bus.write(0xFEB5, 0x5A);
+            assert_eq!(
+                [0xE123, 0xD000, 0xFEB5, 0xFF03].map(|addr| bus.read(addr)),
+                [0x42, 0x99, prohibited, 0xFF],
+                "{:?}",
+                model
+            );
+        }
+    }
+
+    #[test]
+    fn switch_rom_and_ram_banks() {
+        // MBC5+RAM+BATTERY, 128KiB ROM, 32KiB RAM
+        let cart = build_cart(8, |rom| {
+            rom[0x0147] = 0x1B;
+            rom[0x0148] = 0x02;
+            rom[0x0149] = 0x03;
+            for bank in 1..8 {
+                rom[bank * 0x4000] = bank as u8 * 0x11;
+            }
+        });
+        let mut bus = new_bus(cart, HardwareModel::DMG);
+
+        bus.write(0x2000, 0x03);
+        assert_eq!(bus.read(0x4000), 0x33);
+        // RAM is disabled.
+        assert_eq!(bus.read(0xA123), 0xFF);
+
+        bus.write(0x0000, 0x0A);
+        bus.write(0x4000, 0x02);
+        bus.write(0xA123, 0x5A);
+        bus.write(0x4000, 0x00);
+        assert_eq!(bus.read(0xA123), 0x00);
+        bus.write(0x4000, 0x02);
+        assert_eq!(bus.read(0xA123), 0x5A);
+    }
+
+    #[test]
+    fn switch_wram_and_vram_banks() {
+        // CGB only
+        let cart = build_cart(2, |rom| rom[0x0143] = 0xC0);
+        let mut bus = new_bus(cart, HardwareModel::CGBE);
+
+        bus.write(0xFF70, 0x02);
+        bus.write(0xD000, 0x22);
+        bus.write(0xFF70, 0x03);
+        // ECHO RAM mirrors the bank switched in.
+        bus.write(0xF000, 0x33);
+        bus.write(0xFF70, 0x02);
+        assert_eq!(bus.read(0xF000), 0x22);
+
+        bus.write(0xFF4F, 0x01);
+        bus.write(0x8000, 0x44);
+        bus.write(0xFF4F, 0x00);
+        assert_eq!(bus.read(0x8000), 0x00);
+
+        bus.write(0xFF70, 0x03);
+        assert_eq!(bus.read(0xD000), 0x33);
+        bus.write(0xFF4F, 0x01);
+        assert_eq!(bus.read(0x8000), 0x44);
+    }
+
+    #[test]
+    fn oam_dma_bus_conflicts() {
+        // Returns bytes read from ROM and WRAM during OAM DMA from WRAM.
+        let run = |model| {
+            let mut bus = new_bus(build_cart(2, |rom| rom[0x0100] = 0x12), model);
+            for addr in 0xC000..0xC0A0 {
+                bus.write(addr, 0x77);
+            }
+            bus.write(0xD000, 0x55);
+
+            bus.write(0xFF46, 0xC0);
+            // Start-up delay.
+            bus.step(4);
+            bus.step(4);
+            [bus.read(0x0100), bus.read(0xD000)]
+        };
+
+        // ROM and WRAM share the external bus on DMG.
+        assert_eq!(run(HardwareModel::DMG), [0x77, 0x77]);
+        // WRAM has its own bus on CGB.
+        assert_eq!(run(HardwareModel::CGBE), [0x12, 0x77]);
+    }
+
+    #[test]
+    fn frame_sequencer_clocked_by_div() {
+        let mut bus = new_bus(build_cart(2, |_| {}), HardwareModel::DMG);
+        // Trigger CH2 with length 1.
+        bus.write(0xFF26, 0x80);
+        bus.write(0xFF17, 0xF0);
+        bus.write(0xFF16, 0x3F);
+        bus.write(0xFF19, 0xC0);
+
+        // CH2's length counter doesn't expire while DIV keeps being reset.
+        for _ in 0..0x1000 {
+            bus.step(4);
+            bus.write(0xFF04, 0x00);
+        }
+        assert_eq!(bus.cpu_read(0xFF26) & 0x02, 0x02);
+
+        for _ in 0..0x1000 {
+            bus.step(4);
+        }
+        assert_eq!(bus.cpu_read(0xFF26) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn skip_idle_cycles_while_transferring() {
+        struct Unplugged;
+
+        impl SerialLink for Unplugged {
+            fn transfer(&mut self, _byte: u8) -> u8 {
+                0xFF
+            }
+
+            fn poll(&mut self, _byte: u8) -> Option<u8> {
+                None
+            }
+        }
+
+        let mut bus = new_bus(build_cart(2, |_| {}), HardwareModel::DMG);
+        turn_lcd_off(&mut bus);
+
+        // External clock without a link waits forever, e.g. while halted.
+        bus.write(0xFF02, 0x80);
+        assert!(bus.idle_cycles() > 128, "{}", bus.idle_cycles());
+
+        // The link is polled on every machine cycle.
+        bus.replace_serial_link(Some(Box::new(Unplugged)));
+        assert_eq!(bus.idle_cycles(), 0);
+        bus.replace_serial_link(None);
+        assert!(bus.idle_cycles() > 128, "{}", bus.idle_cycles());
+
+        // Internal clock shifts a bit every 128 machine cycles, which
+        // finishes on time.
+        bus.write(0xFF0F, 0x00);
+        bus.write(0xFF02, 0x81);
+        assert!(bus.idle_cycles() < 128, "{}", bus.idle_cycles());
+        let clocks = bus.timer.clocks_until_falling_edge(8, 8);
+        for _ in 0..clocks / 4 - 1 {
+            bus.step(4);
+        }
+        assert_eq!(bus.cpu_read(0xFF0F) & 0x08, 0x00);
+        bus.step(4);
+        assert_eq!(bus.cpu_read(0xFF0F) & 0x08, 0x08);
     }
 }
@@ -0,0 +1,244 @@
+use gb_shared::InterruptType;
+use std::{cell::Cell, ops::RangeInclusive};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    /// ROM bank of `addr`, only takes effect for switchable ROM
+    /// bank [0x4000, 0x7FFF]. `None` matches any bank.
+    pub bank: Option<usize>,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16) -> Self {
+        Self { addr, bank: None }
+    }
+
+    pub fn with_bank(addr: u16, bank: usize) -> Self {
+        Self { addr, bank: Some(bank) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn read(range: RangeInclusive<u16>) -> Self {
+        Self { range, read: true, write: false }
+    }
+
+    pub fn write(range: RangeInclusive<u16>) -> Self {
+        Self { range, read: false, write: true }
+    }
+
+    pub fn read_write(range: RangeInclusive<u16>) -> Self {
+        Self { range, read: true, write: true }
+    }
+
+    fn hit(&self, addr: u16, access: Access) -> bool {
+        let access_matched = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+
+        access_matched && self.range.contains(&addr)
+    }
+}
+
+/// CPU registers, viewed by [`crate::GameBoy::registers`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    /// Lower 4 bits are always zero.
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    /// Address of the next instruction to be executed.
+    pub pc: u16,
+    /// Interrupt master enable flag.
+    pub ime: bool,
+    /// Whether CPU is halted by HALT, until an interrupt is pending.
+    pub halted: bool,
+}
+
+impl Registers {
+    #[inline]
+    pub fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f])
+    }
+
+    #[inline]
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    #[inline]
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    #[inline]
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    /// The instruction at `addr` is about to be executed.
+    Breakpoint { addr: u16, bank: usize },
+    /// `addr` was accessed by the instruction just executed. `value`
+    /// is the value read or written.
+    Watchpoint { addr: u16, value: u8, access: Access },
+    /// The interrupt was serviced, and its handler is about to be executed.
+    Interrupt(InterruptType),
+    /// Run out of the clocks budget.
+    ClocksExhausted,
+}
+
+/// Breakpoints and watchpoints used by [`crate::GameBoy::run_until_break`].
+///
+/// Watchpoints only track memory accesses from CPU, including reading
+/// IF and IE when checking interrupts.
+#[derive(Debug, Default, Clone)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    break_on_interrupt: bool,
+    /// The first watchpoint hit since last taken.
+    watch_hit: Cell<Option<BreakReason>>,
+}
+
+impl Debugger {
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.retain(|bp| *bp != breakpoint);
+    }
+
+    #[inline]
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) {
+        self.watchpoints.retain(|wp| wp != watchpoint);
+    }
+
+    #[inline]
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    #[inline]
+    pub fn set_break_on_interrupt(&mut self, enabled: bool) {
+        self.break_on_interrupt = enabled;
+    }
+
+    #[inline]
+    pub fn break_on_interrupt(&self) -> bool {
+        self.break_on_interrupt
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.break_on_interrupt = false;
+        self.watch_hit.set(None);
+    }
+
+    #[inline]
+    pub(crate) fn on_access(&self, addr: u16, value: u8, access: Access) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+
+        self.check_watchpoints(addr, value, access);
+    }
+
+    fn check_watchpoints(&self, addr: u16, value: u8, access: Access) {
+        if self.watch_hit.get().is_some() {
+            return;
+        }
+
+        if self.watchpoints.iter().any(|wp| wp.hit(addr, access)) {
+            self.watch_hit.set(Some(BreakReason::Watchpoint { addr, value, access }));
+        }
+    }
+
+    #[inline]
+    pub(crate) fn take_watch_hit(&self) -> Option<BreakReason> {
+        self.watch_hit.take()
+    }
+
+    pub(crate) fn find_breakpoint(&self, addr: u16, bank: usize) -> Option<BreakReason> {
+        let banked = (0x4000..=0x7FFF).contains(&addr);
+        self.breakpoints
+            .iter()
+            .any(|bp| bp.addr == addr && (!banked || bp.bank.is_none_or(|b| b == bank)))
+            .then_some(BreakReason::Breakpoint { addr, bank })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_qualified_breakpoint() {
+        let mut debugger = Debugger::default();
+        debugger.add_breakpoint(Breakpoint::with_bank(0x4000, 2));
+        debugger.add_breakpoint(Breakpoint::with_bank(0x0100, 2));
+
+        assert_eq!(debugger.find_breakpoint(0x4000, 1), None);
+        assert_eq!(
+            debugger.find_breakpoint(0x4000, 2),
+            Some(BreakReason::Breakpoint { addr: 0x4000, bank: 2 })
+        );
+        // Bank is ignored out of the switchable ROM bank.
+        assert!(debugger.find_breakpoint(0x0100, 0).is_some());
+    }
+
+    #[test]
+    fn keep_first_watchpoint_hit() {
+        let mut debugger = Debugger::default();
+        debugger.add_watchpoint(Watchpoint::write(0xC000..=0xC0FF));
+        debugger.add_watchpoint(Watchpoint::read(0xFF80..=0xFF80));
+
+        debugger.on_access(0xC000, 0x12, Access::Read);
+        assert_eq!(debugger.take_watch_hit(), None);
+
+        debugger.on_access(0xC010, 0x34, Access::Write);
+        debugger.on_access(0xFF80, 0x56, Access::Read);
+        assert_eq!(
+            debugger.take_watch_hit(),
+            Some(BreakReason::Watchpoint { addr: 0xC010, value: 0x34, access: Access::Write })
+        );
+        assert_eq!(debugger.take_watch_hit(), None);
+    }
+}
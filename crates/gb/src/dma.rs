@@ -1,17 +1,29 @@
 use gb_shared::{Memory, Snapshot};
 
+/// Machine cycles between writing 0xFF46 and the transfer starting.
+const STARTUP_DELAY: u8 = 1;
+
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct DMA {
     value: u8,
+    /// Source address of the ongoing transfer.
+    src: u16,
     /// 160 bytes, transferring each costs 1 machine cycle(4 CPU clock cycles).
     offset: u8,
+    /// Source address of the requested transfer, and machine cycles
+    /// before it starts. The ongoing transfer goes on until then.
+    requested: Option<(u16, u8)>,
 }
 
 impl Memory for DMA {
     fn write(&mut self, _0xff46: u16, value: u8) {
         self.value = value;
-        self.offset = 0;
+        // Sources above 0xDFFF are mapped to WRAM.
+        // @see https://gbdev.io/pandocs/OAM_DMA_Transfer.html#ff46--dma-oam-dma-source-address--start
+        let src = value as u16 * 0x100;
+        let src = if src >= 0xE000 { src - 0x2000 } else { src };
+        self.requested = Some((src, STARTUP_DELAY));
     }
 
     fn read(&self, _0xff46: u16) -> u8 {
@@ -21,54 +33,114 @@ impl Memory for DMA {
 
 impl DMA {
     pub(crate) fn new() -> Self {
-        Self { value: 0, offset: 160 }
+        Self { value: 0, src: 0, offset: 160, requested: None }
     }
 
     pub(crate) fn active(&self) -> bool {
         self.offset < 160
     }
 
-    pub(crate) fn next_addr(&mut self) -> Option<(u16, u16)> {
-        if self.active() {
-            let offset = self.offset as u16;
-            let dst = 0xFE00 + offset;
+    /// Whether a transfer is ongoing or requested.
+    #[inline]
+    pub(crate) fn in_progress(&self) -> bool {
+        self.active() || self.requested.is_some()
+    }
 
-            let addr = self.value as u16 * 0x100;
-            let src = addr + offset;
+    /// Address DMA is reading from in current machine cycle.
+    #[inline]
+    pub(crate) fn current_src(&self) -> Option<u16> {
+        self.active().then(|| self.src + self.offset as u16)
+    }
 
+    /// Called on every machine cycle. Returns the addresses to transfer
+    /// a byte from and to.
+    pub(crate) fn next_addr(&mut self) -> Option<(u16, u16)> {
+        let addr = self.current_src().map(|src| {
+            let dst = 0xFE00 + self.offset as u16;
             self.offset += 1;
+            (src, dst)
+        });
 
-            Some((src, dst))
-        } else {
-            None
+        if let Some((src, delay)) = self.requested {
+            if delay == 0 {
+                self.src = src;
+                self.offset = 0;
+                self.requested = None;
+            } else {
+                self.requested = Some((src, delay - 1));
+            }
         }
+
+        addr
     }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub(crate) struct DmaSnapshot {
     value: u8,
+    src: u16,
     offset: u8,
+    requested: Option<(u16, u8)>,
 }
 
 impl Snapshot for DMA {
     type Snapshot = DmaSnapshot;
 
     fn take_snapshot(&self) -> Self::Snapshot {
-        DmaSnapshot { value: self.value, offset: self.offset }
+        DmaSnapshot {
+            value: self.value,
+            src: self.src,
+            offset: self.offset,
+            requested: self.requested,
+        }
     }
 
     fn restore_snapshot(&mut self, snapshot: Self::Snapshot) {
         self.value = snapshot.value;
+        self.src = snapshot.src;
         self.offset = snapshot.offset;
+        self.requested = snapshot.requested;
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn inactive_on_creation() {
         let dma = super::DMA::new();
         assert!(!dma.active());
     }
+
+    #[test]
+    fn start_after_delay() {
+        let mut dma = DMA::new();
+        dma.write(0xFF46, 0xC1);
+        // The machine cycle of writing, and the delay.
+        assert_eq!(dma.next_addr(), None);
+        assert_eq!(dma.next_addr(), None);
+        assert!(dma.active());
+
+        for offset in 0..160 {
+            assert_eq!(dma.next_addr(), Some((0xC100 + offset, 0xFE00 + offset)));
+        }
+        assert!(!dma.active());
+        assert_eq!(dma.next_addr(), None);
+    }
+
+    #[test]
+    fn restart() {
+        let mut dma = DMA::new();
+        dma.write(0xFF46, 0xC1);
+        for _ in 0..12 {
+            dma.next_addr();
+        }
+
+        // Mapped to WRAM.
+        dma.write(0xFF46, 0xFE);
+        assert_eq!(dma.next_addr(), Some((0xC10A, 0xFE0A)));
+        assert_eq!(dma.next_addr(), Some((0xC10B, 0xFE0B)));
+        assert_eq!(dma.next_addr(), Some((0xDE00, 0xFE00)));
+    }
 }
@@ -1,13 +1,18 @@
-use gb_shared::{box_array, Memory, Snapshot};
+use crate::wram::fill_power_on_garbage;
+use gb_shared::{box_array, HardwareModel, Memory, Snapshot};
 
+#[derive(Clone)]
 pub(crate) struct HighRam {
     /// [FF80, FFFF)
     ram: Box<[u8; 0x80]>,
 }
 
 impl HighRam {
-    pub(crate) fn new() -> Self {
-        Self { ram: box_array![u8; 0x80] }
+    pub(crate) fn new(model: HardwareModel) -> Self {
+        let mut ram = box_array![u8; 0x80];
+        fill_power_on_garbage(ram.as_mut_slice(), model);
+
+        Self { ram }
     }
 }
 
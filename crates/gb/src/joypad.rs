@@ -1,7 +1,7 @@
 use gb_shared::{is_bit_set, Interrupt, InterruptRequest, Memory};
 
 /// The state is true when the value is zero.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Joypad {
     /// - Bit 7: Start
     /// - Bit 6: Select
@@ -1,56 +1,148 @@
+mod boot_rom;
 mod bus;
+mod debugger;
 mod dma;
 mod hram;
 mod joypad;
+mod link;
 mod misc_ram;
+mod page_table;
+mod printer;
+mod scheduler;
 mod serial;
 mod timer;
 mod vdma;
 mod wram;
 
+pub use boot_rom::BootRom;
 use bus::{Bus, BusSnapshot};
+pub use debugger::{Access, BreakReason, Breakpoint, Debugger, Registers, Watchpoint};
 pub use gb_apu::buffer_size_from_sample_rate;
 pub use gb_apu::AudioHandle;
-pub use gb_cartridge::Cartridge;
+pub use gb_cartridge::{Cartridge, RomOwner};
+pub use gb_cpu_sm83::trace::{RichTrace, RingBuffer, TraceMode, TraceRecord, TraceSink, WriteSink};
 use gb_cpu_sm83::{Cpu, CpuSnapshot};
 pub use gb_ppu::FrameHandle;
+pub use gb_shared::HardwareModel;
 use gb_shared::{command::Command, MachineModel, Snapshot};
+pub use link::LinkCable;
+#[cfg(not(target_family = "wasm"))]
+pub use link::TcpLink;
+pub use printer::{Printer, Printout, PRINTOUT_WIDTH};
+pub use serial::{SerialLink, SerialOutputHandle};
 
+/// What to run, built by [`Manifest::new`] and the `with_*` setters,
+/// so that adding options doesn't break existing code.
+#[non_exhaustive]
 pub struct Manifest {
     pub cart: Cartridge,
     pub sample_rate: Option<u32>,
+    /// Run the boot ROM from 0x0000 if provided. Otherwise, start from
+    /// 0x0100 with post-boot state.
+    pub boot_rom: Option<BootRom>,
+    /// Hardware to emulate. If not provided, it follows the boot ROM,
+    /// or the cartridge if boot ROM is not provided either.
+    ///
+    /// It overrides the cartridge header, e.g. DMG cartridges run on CGB
+    /// in DMG compatibility mode, and CGB cartridges run on DMG in DMG
+    /// mode, where CGB only ones usually show an incompatible message.
+    pub model: Option<HardwareModel>,
+}
+
+impl Manifest {
+    /// Run `cart` with defaults, i.e. without audio output or boot ROM.
+    pub fn new(cart: Cartridge) -> Self {
+        Self { cart, sample_rate: None, boot_rom: None, model: None }
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn with_boot_rom(mut self, boot_rom: BootRom) -> Self {
+        self.boot_rom = Some(boot_rom);
+        self
+    }
+
+    pub fn with_model(mut self, model: HardwareModel) -> Self {
+        self.model = Some(model);
+        self
+    }
 }
 
 pub struct GameBoy {
     cpu: Cpu<Bus>,
-    bus: Bus,
     clocks: u32,
     cart_checksum: u16,
 }
 
 impl GameBoy {
     pub fn new(manifest: Manifest) -> Self {
-        let Manifest { cart, sample_rate } = manifest;
+        let Manifest { cart, sample_rate, boot_rom, model } = manifest;
 
         let cart_header_checksum = cart.header.checksum;
         let cart_global_checksum = cart.header.global_checksum;
-        let machine_model = cart.machine_model();
-        let bus = Bus::new(cart, sample_rate);
+        let model = model.unwrap_or_else(|| {
+            match &boot_rom {
+                Some(boot_rom) => boot_rom.machine_model(),
+                None => cart.machine_model(),
+            }
+            .into()
+        });
+        if let Some(boot_rom) = &boot_rom {
+            if boot_rom.machine_model() != model.machine_model() {
+                log::warn!("Boot ROM doesn't match {:?}", model);
+            }
+        }
+        // CGB runs DMG cartridges in DMG compatibility mode, while DMG
+        // runs any cartridge in DMG mode.
+        let machine_model = match model.machine_model() {
+            MachineModel::DMG => MachineModel::DMG,
+            MachineModel::CGB => cart.machine_model(),
+        };
+        let run_boot_rom = boot_rom.is_some();
+        let bus = Bus::new(cart, model, machine_model, boot_rom, sample_rate);
 
-        let cpu = match machine_model {
-            MachineModel::DMG => Cpu::new_dmg(bus.clone(), cart_header_checksum),
-            MachineModel::CGB => Cpu::new_cgb(bus.clone()),
+        let cpu = if run_boot_rom {
+            Cpu::new_with_boot_rom(bus, machine_model)
+        } else {
+            Cpu::new_post_boot(bus, model, machine_model, cart_header_checksum)
         };
 
-        Self { cpu, bus, clocks: 0, cart_checksum: cart_global_checksum }
+        Self { cpu, clocks: 0, cart_checksum: cart_global_checksum }
+    }
+
+    /// Make an independent copy running from the current state, which is
+    /// much cheaper than restoring a snapshot. The cartridge ROM is shared.
+    ///
+    /// The fork is headless and disconnected, i.e. handles, the serial
+    /// link and the trace sink are not copied.
+    pub fn fork(&self) -> Self {
+        Self { cpu: self.cpu.clone(), clocks: self.clocks, cart_checksum: self.cart_checksum }
+    }
+
+    #[inline]
+    fn bus(&self) -> &Bus {
+        self.cpu.bus()
+    }
+
+    #[inline]
+    fn bus_mut(&mut self) -> &mut Bus {
+        self.cpu.bus_mut()
+    }
+
+    #[inline]
+    pub fn model(&self) -> HardwareModel {
+        self.bus().model
     }
 
     pub fn replace_frame_handle(
         &mut self,
         handle: Option<Box<FrameHandle>>,
     ) -> Option<Box<FrameHandle>> {
-        let prev = self.bus.ppu.frame_handle.take();
-        self.bus.ppu.frame_handle = handle;
+        let prev = self.bus_mut().ppu.frame_handle.take();
+        self.bus_mut().ppu.frame_handle = handle;
         prev
     }
 
@@ -58,8 +150,40 @@ impl GameBoy {
         &mut self,
         handle: Option<Box<AudioHandle>>,
     ) -> Option<Box<AudioHandle>> {
-        let prev = self.bus.apu.audio_handle.take();
-        self.bus.apu.audio_handle = handle;
+        // Run clocks passed with the previous handle.
+        self.bus_mut().apu.sync();
+        let prev = self.bus_mut().apu.audio_handle.take();
+        self.bus_mut().apu.audio_handle = handle;
+        prev
+    }
+
+    /// Connect the serial port to the other end of the link cable.
+    /// Pass `None` to disconnect.
+    pub fn replace_serial_link(
+        &mut self,
+        link: Option<Box<dyn SerialLink>>,
+    ) -> Option<Box<dyn SerialLink>> {
+        self.bus_mut().replace_serial_link(link)
+    }
+
+    /// Start or stop capturing bytes sent over serial, which can be
+    /// taken by [`GameBoy::take_serial_output`]. Captured bytes are
+    /// discarded when stopped.
+    pub fn capture_serial_output(&mut self, enabled: bool) {
+        self.bus_mut().serial.output = if enabled { Some(vec![]) } else { None };
+    }
+
+    /// Take bytes sent over serial since last taken.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.bus_mut().serial.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn replace_serial_output_handle(
+        &mut self,
+        handle: Option<Box<SerialOutputHandle>>,
+    ) -> Option<Box<SerialOutputHandle>> {
+        let prev = self.bus_mut().serial.output_handle.take();
+        self.bus_mut().serial.output_handle = handle;
         prev
     }
 
@@ -70,12 +194,138 @@ impl GameBoy {
 
     #[inline]
     pub fn coerce_bw_colors_on_dmg(&mut self, coerce: bool, immediate: bool) {
-        self.bus.ppu.coerce_bw_colors_on_dmg(coerce, immediate)
+        self.bus_mut().ppu.coerce_bw_colors_on_dmg(coerce, immediate)
     }
 
     #[inline]
     pub fn exec_command(&mut self, command: Command) {
-        self.bus.exec_command(command);
+        self.bus_mut().exec_command(command);
+    }
+
+    pub fn registers(&self) -> Registers {
+        let cpu = &self.cpu;
+        let [a, f] = cpu.af().to_be_bytes();
+        let [b, c] = cpu.bc().to_be_bytes();
+        let [d, e] = cpu.de().to_be_bytes();
+        let [h, l] = cpu.hl().to_be_bytes();
+        Registers {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: cpu.sp(),
+            pc: cpu.pc(),
+            ime: cpu.ime(),
+            halted: cpu.halted(),
+        }
+    }
+
+    /// Modify registers, e.g. from what [`GameBoy::registers`] returns.
+    /// If PC changes, the opcode there is fetched without consuming clocks.
+    pub fn set_registers(&mut self, registers: Registers) {
+        let cpu = &mut self.cpu;
+        cpu.set_af(registers.af());
+        cpu.set_bc(registers.bc());
+        cpu.set_de(registers.de());
+        cpu.set_hl(registers.hl());
+        cpu.set_sp(registers.sp);
+        cpu.set_ime(registers.ime);
+        cpu.set_halted(registers.halted);
+        if cpu.pc() != registers.pc {
+            cpu.set_pc(registers.pc);
+        }
+    }
+
+    /// Whether CPU is in STOP mode.
+    #[inline]
+    pub fn stopped(&self) -> bool {
+        self.cpu.stopped()
+    }
+
+    /// Read memory as CPU sees it, but without side effects, e.g. hitting
+    /// watchpoints.
+    #[inline]
+    pub fn peek(&self, addr: u16) -> u8 {
+        gb_shared::Bus::peek(self.bus(), addr)
+    }
+
+    /// Trace every instruction into `sink` before it gets executed.
+    /// Pass `None` to disable tracing. Return the previous sink.
+    pub fn replace_trace_sink(
+        &mut self,
+        sink: Option<Box<dyn TraceSink>>,
+        mode: TraceMode,
+    ) -> Option<Box<dyn TraceSink>> {
+        self.cpu.replace_trace_sink(sink, mode)
+    }
+
+    #[inline]
+    pub fn debugger(&self) -> &Debugger {
+        &self.bus().debugger
+    }
+
+    #[inline]
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.bus_mut().debugger
+    }
+
+    /// Run until hitting any breakpoint or watchpoint set in [`Debugger`],
+    /// or running out of `clocks`.
+    ///
+    /// Breakpoints are checked after each instruction, so running again
+    /// from a breakpoint will not stop at it immediately.
+    pub fn run_until_break(&mut self, clocks: u32) -> BreakReason {
+        let reason = self.step_until_break(clocks);
+        self.sync();
+        reason
+    }
+
+    fn step_until_break(&mut self, clocks: u32) -> BreakReason {
+        // Discard hits during normal running.
+        self.bus_mut().debugger.take_watch_hit();
+
+        let mut elapsed = 0u32;
+        loop {
+            self.cpu.step();
+            elapsed += self.cpu.take_clocks() as u32;
+
+            if let Some(reason) = self.bus_mut().debugger.take_watch_hit() {
+                return reason;
+            }
+
+            if let Some(interrupt_type) = self.cpu.take_serviced_interrupt() {
+                if self.bus().debugger.break_on_interrupt() {
+                    return BreakReason::Interrupt(interrupt_type);
+                }
+            }
+
+            if self.cpu.fetched() {
+                let pc = self.cpu.pc();
+                let bank = gb_shared::Bus::rom_bank(self.bus(), pc);
+                if let Some(reason) = self.bus_mut().debugger.find_breakpoint(pc, bank) {
+                    return reason;
+                }
+            }
+
+            if elapsed >= clocks {
+                return BreakReason::ClocksExhausted;
+            }
+        }
+    }
+
+    /// Run at least `clocks`, and return the clocks actually run.
+    pub(crate) fn run_clocks(&mut self, clocks: u32) -> u32 {
+        let mut elapsed = 0;
+        while elapsed < clocks {
+            self.cpu.step();
+            elapsed += self.cpu.take_clocks() as u32;
+        }
+        self.sync();
+        elapsed
     }
 
     pub fn continue_clocks(&mut self, clocks: u32) {
@@ -85,10 +335,18 @@ impl GameBoy {
             self.clocks = finished_clocks % clocks;
 
             if finished_clocks >= clocks {
+                self.sync();
                 return;
             }
         }
     }
+
+    /// Run components up to now, which may lag behind CPU, so that they
+    /// can be viewed, and pending audio samples are delivered.
+    fn sync(&mut self) {
+        self.bus_mut().run_pending();
+        self.bus_mut().apu.sync();
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -110,14 +368,14 @@ impl Snapshot for GameBoy {
 
     fn take_snapshot(&self) -> Self::Snapshot {
         Self::Snapshot {
-            bus: self.bus.take_snapshot(),
+            bus: self.bus().take_snapshot(),
             cpu: self.cpu.take_snapshot(),
             cart_checksum: self.cart_checksum,
         }
     }
 
     fn restore_snapshot(&mut self, snapshot: Self::Snapshot) {
-        self.bus.restore_snapshot(snapshot.bus);
+        self.bus_mut().restore_snapshot(snapshot.bus);
         self.cpu.restore_snapshot(snapshot.cpu);
     }
 }
@@ -140,10 +398,10 @@ impl TryFrom<&GameBoySnapshot> for Vec<u8> {
 
 impl GameBoy {
     pub fn suspend_cartridge(&self) -> Option<Vec<u8>> {
-        self.bus.cart.suspend()
+        self.bus().cart.suspend()
     }
 
     pub fn resume_cartridge(&mut self, data: &[u8]) -> anyhow::Result<()> {
-        self.bus.cart.resume(data)
+        self.bus_mut().cart.resume(data)
     }
 }
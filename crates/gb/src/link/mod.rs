@@ -0,0 +1,109 @@
+#[cfg(not(target_family = "wasm"))]
+mod tcp;
+
+use crate::{GameBoy, SerialLink};
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_family = "wasm"))]
+pub use tcp::TcpLink;
+
+#[derive(Debug, Default)]
+struct Wire {
+    /// Byte to shift out of each end using external clock, while it's waiting.
+    waiting: [Option<u8>; 2],
+    /// Byte received by each end using external clock.
+    received: [Option<u8>; 2],
+}
+
+struct CableEnd {
+    wire: Arc<Mutex<Wire>>,
+    side: usize,
+}
+
+impl SerialLink for CableEnd {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut wire = self.wire.lock().unwrap();
+        let peer = 1 - self.side;
+        match wire.waiting[peer].take() {
+            Some(peer_byte) => {
+                wire.received[peer] = Some(byte);
+                peer_byte
+            }
+            // The other end is not ready.
+            None => 0xFF,
+        }
+    }
+
+    fn poll(&mut self, byte: u8) -> Option<u8> {
+        let mut wire = self.wire.lock().unwrap();
+        let received = wire.received[self.side].take();
+        wire.waiting[self.side] = if received.is_some() { None } else { Some(byte) };
+        received
+    }
+}
+
+/// Connect two [`GameBoy`]s via their serial ports, and run them in lock-step.
+/// Either one can be the master by using internal clock.
+pub struct LinkCable {
+    gameboys: [GameBoy; 2],
+    /// Clocks each one runs before switching to the other.
+    granularity: u32,
+    /// Clocks each one has run ahead.
+    overshoot: [u32; 2],
+}
+
+impl LinkCable {
+    /// Default granularity, which is 1/8 of a bit transferred at 8192Hz.
+    pub const DEFAULT_GRANULARITY: u32 = 64;
+
+    pub fn new(mut gb1: GameBoy, mut gb2: GameBoy) -> Self {
+        let wire = Arc::new(Mutex::new(Wire::default()));
+        gb1.replace_serial_link(Some(Box::new(CableEnd { wire: wire.clone(), side: 0 })));
+        gb2.replace_serial_link(Some(Box::new(CableEnd { wire, side: 1 })));
+
+        Self { gameboys: [gb1, gb2], granularity: Self::DEFAULT_GRANULARITY, overshoot: [0; 2] }
+    }
+
+    /// Smaller granularity gives more accurate timing for the end
+    /// using external clock, at the cost of performance.
+    pub fn set_granularity(&mut self, clocks: u32) {
+        self.granularity = clocks.max(4);
+    }
+
+    #[inline]
+    pub fn gameboys(&self) -> &[GameBoy; 2] {
+        &self.gameboys
+    }
+
+    #[inline]
+    pub fn gameboys_mut(&mut self) -> &mut [GameBoy; 2] {
+        &mut self.gameboys
+    }
+
+    /// Unplug the cable.
+    pub fn disconnect(self) -> [GameBoy; 2] {
+        let mut gameboys = self.gameboys;
+        for gb in gameboys.iter_mut() {
+            gb.replace_serial_link(None);
+        }
+        gameboys
+    }
+
+    /// Run both for `clocks`.
+    pub fn continue_clocks(&mut self, clocks: u32) {
+        let mut remaining = clocks;
+        while remaining > 0 {
+            let chunk = remaining.min(self.granularity);
+            remaining -= chunk;
+
+            for (gb, overshoot) in self.gameboys.iter_mut().zip(self.overshoot.iter_mut()) {
+                if *overshoot >= chunk {
+                    *overshoot -= chunk;
+                    continue;
+                }
+
+                let target = chunk - *overshoot;
+                *overshoot = gb.run_clocks(target) - target;
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use crate::SerialLink;
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+const MAGIC: &[u8; 4] = b"GBLK";
+const VERSION: u8 = 2;
+/// Interval to exchange clocks and measure round trip again.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wire protocol. Each message is a tag byte followed by its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Message {
+    /// Handshake, followed by [`MAGIC`] and [`VERSION`].
+    Hello,
+    /// Round trip, used to measure latency.
+    Ping,
+    Pong,
+    /// Microseconds elapsed since the handshake of the sender, used to
+    /// measure the offset between clocks of both ends.
+    Sync(u64),
+    /// Byte shifted out by the end using internal clock.
+    Transfer(u8),
+    /// Byte shifted out by the end using external clock in response.
+    Reply(u8),
+}
+
+impl Message {
+    fn write_to(self, writer: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            Message::Hello => {
+                writer.write_all(&[0x00])?;
+                writer.write_all(MAGIC)?;
+                writer.write_all(&[VERSION])
+            }
+            Message::Ping => writer.write_all(&[0x01]),
+            Message::Pong => writer.write_all(&[0x02]),
+            Message::Transfer(byte) => writer.write_all(&[0x03, byte]),
+            Message::Reply(byte) => writer.write_all(&[0x04, byte]),
+            Message::Sync(micros) => {
+                let mut buf = [0x05; 9];
+                buf[1..].copy_from_slice(&micros.to_le_bytes());
+                writer.write_all(&buf)
+            }
+        }
+    }
+
+    fn read_from(reader: &mut impl Read) -> anyhow::Result<Self> {
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        let message = match tag[0] {
+            0x00 => {
+                let mut hello = [0u8; 5];
+                reader.read_exact(&mut hello)?;
+                if &hello[..4] != MAGIC {
+                    anyhow::bail!("Not a link cable peer");
+                }
+                if hello[4] != VERSION {
+                    anyhow::bail!("Unsupported protocol version {}", hello[4]);
+                }
+                Message::Hello
+            }
+            0x01 => Message::Ping,
+            0x02 => Message::Pong,
+            0x03 | 0x04 => {
+                let mut byte = [0u8; 1];
+                reader.read_exact(&mut byte)?;
+                if tag[0] == 0x03 {
+                    Message::Transfer(byte[0])
+                } else {
+                    Message::Reply(byte[0])
+                }
+            }
+            0x05 => {
+                let mut micros = [0u8; 8];
+                reader.read_exact(&mut micros)?;
+                Message::Sync(u64::from_le_bytes(micros))
+            }
+            tag => anyhow::bail!("Unknown message {:#04X}", tag),
+        };
+
+        Ok(message)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Byte to shift out while using external clock and waiting.
+    waiting: Option<u8>,
+    /// Byte received while using external clock.
+    received: Option<u8>,
+    /// Reply to the last transfer while using internal clock.
+    reply: Option<u8>,
+    /// The last transfer timed out, and its reply is not received yet.
+    late: bool,
+    /// When the last ping was sent.
+    ping: Option<Instant>,
+    round_trip: Duration,
+    /// Clock of the other end minus the one of this end, in microseconds.
+    clock_offset: i64,
+    closed: bool,
+}
+
+impl State {
+    fn sync_clock(&mut self, peer_micros: u64, epoch: Instant) {
+        // The peer sent its clock half a round trip ago.
+        let peer = peer_micros as i64 + self.round_trip.as_micros() as i64 / 2;
+        self.clock_offset = peer - epoch.elapsed().as_micros() as i64;
+    }
+}
+
+/// Link cable over TCP, connecting two emulator processes.
+///
+/// Messages from the other end are handled in a background thread,
+/// so that the end using internal clock gets replied even if this
+/// end is not waiting for a transfer, as real hardware does.
+///
+/// Clocks of both ends are exchanged in handshake, and again every
+/// [`SYNC_INTERVAL`] along with the round trip, see [`TcpLink::clock_offset`].
+///
+/// A transfer blocks the emulation until the reply arrives, which is
+/// about one round trip. It's bounded by `tolerance`, and once a transfer
+/// times out, following ones receive 0xFF without blocking until the
+/// late reply arrives, so a lagging peer stalls this end by at most
+/// `tolerance` instead of `tolerance` per byte.
+pub struct TcpLink {
+    stream: TcpStream,
+    state: Arc<(Mutex<State>, Condvar)>,
+    tolerance: Duration,
+    /// Start of the clock sent to the other end.
+    epoch: Instant,
+    last_sync: Instant,
+}
+
+impl TcpLink {
+    /// `tolerance` is the maximum time to wait for the reply of a
+    /// transfer, after which 0xFF is received as if disconnected.
+    pub fn connect(addr: impl ToSocketAddrs, tolerance: Duration) -> anyhow::Result<Self> {
+        Self::handshake(TcpStream::connect(addr)?, tolerance)
+    }
+
+    /// Accept a connection from [`TcpLink::connect`].
+    pub fn accept(listener: &TcpListener, tolerance: Duration) -> anyhow::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Self::handshake(stream, tolerance)
+    }
+
+    fn handshake(mut stream: TcpStream, tolerance: Duration) -> anyhow::Result<Self> {
+        stream.set_nodelay(true)?;
+        let epoch = Instant::now();
+
+        Message::Hello.write_to(&mut stream)?;
+        if Message::read_from(&mut stream)? != Message::Hello {
+            anyhow::bail!("Handshake failed");
+        }
+
+        let start = Instant::now();
+        Message::Ping.write_to(&mut stream)?;
+        loop {
+            match Message::read_from(&mut stream)? {
+                Message::Ping => Message::Pong.write_to(&mut stream)?,
+                Message::Pong => break,
+                message => anyhow::bail!("Unexpected {:?} during handshake", message),
+            }
+        }
+        let round_trip = start.elapsed();
+        if round_trip > tolerance {
+            log::warn!("Link round trip {:?} exceeds tolerance {:?}", round_trip, tolerance);
+        }
+
+        let mut state = State { round_trip, ..Default::default() };
+        Message::Sync(epoch.elapsed().as_micros() as u64).write_to(&mut stream)?;
+        match Message::read_from(&mut stream)? {
+            Message::Sync(micros) => state.sync_clock(micros, epoch),
+            message => anyhow::bail!("Unexpected {:?} during handshake", message),
+        }
+
+        let state = Arc::new((Mutex::new(state), Condvar::new()));
+        let reader = stream.try_clone()?;
+        let thread_state = state.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = Self::serve(reader, &thread_state, epoch) {
+                log::info!("Link closed: {}", err);
+            }
+            thread_state.0.lock().unwrap().closed = true;
+            thread_state.1.notify_all();
+        });
+
+        Ok(Self { stream, state, tolerance, epoch, last_sync: Instant::now() })
+    }
+
+    fn serve(
+        mut stream: TcpStream,
+        state: &(Mutex<State>, Condvar),
+        epoch: Instant,
+    ) -> anyhow::Result<()> {
+        loop {
+            let message = Message::read_from(&mut stream)?;
+            let mut guard = state.0.lock().unwrap();
+            match message {
+                Message::Transfer(byte) => {
+                    let reply = match guard.waiting.take() {
+                        Some(out) => {
+                            guard.received = Some(byte);
+                            out
+                        }
+                        // This end is not ready.
+                        None => 0xFF,
+                    };
+                    Message::Reply(reply).write_to(&mut stream)?;
+                }
+                Message::Reply(byte) => {
+                    guard.reply = Some(byte);
+                    guard.late = false;
+                    state.1.notify_all();
+                }
+                Message::Ping => Message::Pong.write_to(&mut stream)?,
+                Message::Pong => {
+                    if let Some(ping) = guard.ping.take() {
+                        guard.round_trip = ping.elapsed();
+                    }
+                }
+                Message::Sync(micros) => guard.sync_clock(micros, epoch),
+                Message::Hello => {}
+            }
+        }
+    }
+
+    /// The latest round trip time.
+    pub fn round_trip(&self) -> Duration {
+        self.state.0.lock().unwrap().round_trip
+    }
+
+    /// Microseconds the clock of the other end is ahead of this end,
+    /// which are negative if behind.
+    pub fn clock_offset(&self) -> i64 {
+        self.state.0.lock().unwrap().clock_offset
+    }
+
+    /// Exchange clocks and measure round trip again if it's time to.
+    fn sync_if_due(&mut self, state: &mut State) -> std::io::Result<()> {
+        if self.last_sync.elapsed() < SYNC_INTERVAL {
+            return Ok(());
+        }
+
+        self.last_sync = Instant::now();
+        state.ping = Some(self.last_sync);
+        Message::Ping.write_to(&mut self.stream)?;
+        Message::Sync(self.epoch.elapsed().as_micros() as u64).write_to(&mut self.stream)
+    }
+
+    pub fn connected(&self) -> bool {
+        !self.state.0.lock().unwrap().closed
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let shared = self.state.clone();
+        let (lock, cvar) = &*shared;
+        let mut state = lock.lock().unwrap();
+        if state.closed || state.late {
+            return 0xFF;
+        }
+
+        state.reply = None;
+        if self.sync_if_due(&mut state).is_err()
+            || Message::Transfer(byte).write_to(&mut self.stream).is_err()
+        {
+            return 0xFF;
+        }
+
+        let (mut state, _) = cvar
+            .wait_timeout_while(state, self.tolerance, |state| {
+                state.reply.is_none() && !state.closed
+            })
+            .unwrap();
+        state.reply.take().unwrap_or_else(|| {
+            log::warn!("Link transfer timed out");
+            state.late = !state.closed;
+            0xFF
+        })
+    }
+
+    fn poll(&mut self, byte: u8) -> Option<u8> {
+        let state = self.state.clone();
+        let mut state = state.0.lock().unwrap();
+        // Errors are left to the background thread, which closes the link.
+        let _ = self.sync_if_due(&mut state);
+        let received = state.received.take();
+        state.waiting = if received.is_some() { None } else { Some(byte) };
+        received
+    }
+}
+
+impl Drop for TcpLink {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tolerance = Duration::from_secs(1);
+        let server = std::thread::spawn(move || TcpLink::accept(&listener, tolerance).unwrap());
+        let client = TcpLink::connect(addr, tolerance).unwrap();
+
+        (server.join().unwrap(), client)
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let messages = [
+            Message::Hello,
+            Message::Ping,
+            Message::Pong,
+            Message::Transfer(0x12),
+            Message::Reply(0x34),
+            Message::Sync(0x0123_4567_89AB_CDEF),
+        ];
+        let mut buf = vec![];
+        for message in messages {
+            message.write_to(&mut buf).unwrap();
+        }
+
+        let mut reader = buf.as_slice();
+        for message in messages {
+            assert_eq!(Message::read_from(&mut reader).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn reject_bad_hello() {
+        let mut reader: &[u8] = &[0x00, b'G', b'B', b'X', b'X', VERSION];
+        assert!(Message::read_from(&mut reader).is_err());
+    }
+
+    #[test]
+    fn exchange_bytes() {
+        let (mut master, mut slave) = pair();
+
+        // Not waiting yet.
+        assert_eq!(master.transfer(0x12), 0xFF);
+
+        assert_eq!(slave.poll(0x34), None);
+        assert_eq!(master.transfer(0x56), 0x34);
+        assert_eq!(slave.poll(0x34), Some(0x56));
+    }
+
+    #[test]
+    fn sync_clocks() {
+        let (mut master, slave) = pair();
+
+        // Both ends start their clocks at nearly the same time on localhost.
+        assert!(master.clock_offset().abs() < 100_000, "{}", master.clock_offset());
+        assert!(slave.clock_offset().abs() < 100_000, "{}", slave.clock_offset());
+
+        master.last_sync -= SYNC_INTERVAL;
+        master.transfer(0x12);
+        assert!(master.last_sync.elapsed() < SYNC_INTERVAL);
+        assert!(master.state.0.lock().unwrap().ping.is_none());
+    }
+
+    #[test]
+    fn stall_once_for_lagging_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+        // A peer which finishes handshake, but never replies.
+        let peer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            Message::Hello.write_to(&mut stream).unwrap();
+            Message::Ping.write_to(&mut stream).unwrap();
+            loop {
+                match Message::read_from(&mut stream).unwrap() {
+                    Message::Ping => Message::Pong.write_to(&mut stream).unwrap(),
+                    Message::Sync(_) => break,
+                    _ => {}
+                }
+            }
+            Message::Sync(0).write_to(&mut stream).unwrap();
+            done_rx.recv().unwrap();
+        });
+
+        let tolerance = Duration::from_millis(100);
+        let mut link = TcpLink::connect(addr, tolerance).unwrap();
+        let start = Instant::now();
+        assert_eq!(link.transfer(0x12), 0xFF);
+        assert!(start.elapsed() >= tolerance);
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(link.transfer(0x12), 0xFF);
+        }
+        assert!(start.elapsed() < tolerance);
+
+        done_tx.send(()).unwrap();
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn receive_0xff_after_disconnected() {
+        let (mut master, slave) = pair();
+        drop(slave);
+
+        assert_eq!(master.transfer(0x12), 0xFF);
+    }
+}
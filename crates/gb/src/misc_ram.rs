@@ -1,7 +1,14 @@
-use gb_shared::{MachineModel, Memory, Snapshot};
+use gb_shared::{HardwareModel, MachineModel, Memory, Snapshot};
 
+/// Undocumented registers on CGB.
+///
+/// @see https://gbdev.io/pandocs/CGB_Registers.html#undocumented-registers
+#[derive(Clone)]
 pub(crate) struct MiscRam {
-    machine_model: MachineModel,
+    /// FF72, FF73 and FF75 are available on CGB in both modes.
+    cgb_hardware: bool,
+    /// FF74 is available in CGB mode only.
+    pub(crate) cgb_mode: bool,
     reg_ff72: u8,
     reg_ff73: u8,
     reg_ff74: u8,
@@ -9,33 +16,38 @@ pub(crate) struct MiscRam {
 }
 
 impl MiscRam {
-    pub(crate) fn new(machine_model: MachineModel) -> Self {
-        Self { machine_model, reg_ff72: 0, reg_ff73: 0, reg_ff74: 0, reg_ff75: 0 }
+    pub(crate) fn new(model: HardwareModel, machine_model: MachineModel) -> Self {
+        Self {
+            cgb_hardware: model.machine_model() == MachineModel::CGB,
+            cgb_mode: machine_model == MachineModel::CGB,
+            reg_ff72: 0,
+            reg_ff73: 0,
+            reg_ff74: 0,
+            reg_ff75: 0,
+        }
     }
 }
 
 impl Memory for MiscRam {
     fn write(&mut self, addr: u16, value: u8) {
-        let is_cgb = self.machine_model == MachineModel::CGB;
-
         match addr {
-            0xFF72 if is_cgb => self.reg_ff72 = value,
-            0xFF73 if is_cgb => self.reg_ff73 = value,
-            0xFF74 if is_cgb => self.reg_ff74 = value,
-            0xFF75 if is_cgb => self.reg_ff75 = value,
+            0xFF72 if self.cgb_hardware => self.reg_ff72 = value,
+            0xFF73 if self.cgb_hardware => self.reg_ff73 = value,
+            0xFF74 if self.cgb_mode => self.reg_ff74 = value,
+            0xFF75 if self.cgb_hardware => self.reg_ff75 = value & 0x70,
+            0xFF72..=0xFF75 => {}
             _ => unreachable!("Invalid MiscRAM write {:#X} {:#X}", addr, value),
         }
     }
 
     fn read(&self, addr: u16) -> u8 {
-        let is_cgb = self.machine_model == MachineModel::CGB;
-
         match addr {
-            0xFF72 if is_cgb => self.reg_ff72,
-            0xFF73 if is_cgb => self.reg_ff73,
-            0xFF74 if is_cgb => self.reg_ff74,
-            0xFF75 if is_cgb => 0x8F | self.reg_ff75,
-            _ => unreachable!("Invalid MiscRAM write {:#X}", addr),
+            0xFF72 if self.cgb_hardware => self.reg_ff72,
+            0xFF73 if self.cgb_hardware => self.reg_ff73,
+            0xFF74 if self.cgb_mode => self.reg_ff74,
+            0xFF75 if self.cgb_hardware => 0x8F | self.reg_ff75,
+            0xFF72..=0xFF75 => 0xFF,
+            _ => unreachable!("Invalid MiscRAM read {:#X}", addr),
         }
     }
 }
@@ -67,3 +79,32 @@ impl Snapshot for MiscRam {
         self.reg_ff75 = snapshot.reg_ff75;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn availability() {
+        let mut cgb = MiscRam::new(HardwareModel::CGBE, MachineModel::CGB);
+        for addr in 0xFF72..=0xFF75 {
+            cgb.write(addr, 0x00);
+        }
+        assert_eq!([0xFF72, 0xFF73, 0xFF74, 0xFF75].map(|addr| cgb.read(addr)), [0, 0, 0, 0x8F]);
+
+        let mut compatibility = MiscRam::new(HardwareModel::CGBE, MachineModel::DMG);
+        for addr in 0xFF72..=0xFF75 {
+            compatibility.write(addr, 0x00);
+        }
+        assert_eq!(
+            [0xFF72, 0xFF73, 0xFF74, 0xFF75].map(|addr| compatibility.read(addr)),
+            [0, 0, 0xFF, 0x8F]
+        );
+
+        let mut dmg = MiscRam::new(HardwareModel::DMG, MachineModel::DMG);
+        for addr in 0xFF72..=0xFF75 {
+            dmg.write(addr, 0x00);
+            assert_eq!(dmg.read(addr), 0xFF);
+        }
+    }
+}
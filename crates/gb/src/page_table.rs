@@ -0,0 +1,69 @@
+use std::ops::RangeInclusive;
+
+/// Memory a 256-byte page is mapped to, which the bus accesses directly
+/// without going through components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Page {
+    /// Offset in the cartridge ROM. Writes are MBC registers.
+    Rom(u32),
+    /// Offset in VRAM of all banks.
+    Vram(u32),
+    /// Cartridge RAM bank, and offset in it.
+    Sram(u16, u16),
+    /// Offset in WRAM of all banks.
+    Wram(u32),
+    /// IO registers, OAM, memory with side effects, etc.
+    Slow,
+}
+
+#[derive(Clone)]
+pub(crate) struct PageTable {
+    pages: [Page; 256],
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self { pages: [Page::Slow; 256] }
+    }
+}
+
+impl PageTable {
+    #[inline]
+    pub(crate) fn get(&self, addr: u16) -> Page {
+        self.pages[addr as usize >> 8]
+    }
+
+    /// Map pages in `range` by their offsets from the start of `range`.
+    pub(crate) fn map(&mut self, range: RangeInclusive<u16>, page: impl Fn(usize) -> Page) {
+        let start = *range.start() as usize >> 8;
+        let end = *range.end() as usize >> 8;
+        for (nth, entry) in self.pages[start..=end].iter_mut().enumerate() {
+            *entry = page(nth << 8);
+        }
+    }
+
+    pub(crate) fn unmap(&mut self, range: RangeInclusive<u16>) {
+        self.map(range, |_| Page::Slow);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_by_offset() {
+        let mut pages = PageTable::default();
+        pages.map(0xD000..=0xDFFF, |offset| Page::Wram(0x1000 + offset as u32));
+
+        assert_eq!(pages.get(0xCFFF), Page::Slow);
+        assert_eq!(pages.get(0xD000), Page::Wram(0x1000));
+        assert_eq!(pages.get(0xD1FF), Page::Wram(0x1100));
+        assert_eq!(pages.get(0xDFFF), Page::Wram(0x1F00));
+        assert_eq!(pages.get(0xE000), Page::Slow);
+
+        pages.unmap(0xD000..=0xD0FF);
+        assert_eq!(pages.get(0xD000), Page::Slow);
+        assert_eq!(pages.get(0xD100), Page::Wram(0x1100));
+    }
+}
@@ -0,0 +1,402 @@
+//! Game Boy Printer attached to the serial port.
+//!
+//! @see https://gbdev.io/pandocs/Gameboy_Printer.html
+
+use crate::SerialLink;
+use std::sync::{Arc, Mutex};
+
+pub const PRINTOUT_WIDTH: usize = 160;
+/// 20 tiles of 2bpp.
+const BYTES_PER_TILE_ROW: usize = 20 * 16;
+/// 8KiB RAM can hold 9 data packets, i.e. 160x144 image.
+const BUFFER_CAPACITY: usize = BYTES_PER_TILE_ROW * 2 * 9;
+/// Status inquiries answered with busy after printing.
+const BUSY_INQUIRIES: u8 = 2;
+
+/// Status bits.
+const STATUS_CHECKSUM_ERROR: u8 = 0b1;
+const STATUS_PRINTING: u8 = 0b10;
+const STATUS_IMAGE_FULL: u8 = 0b100;
+const STATUS_UNPROCESSED: u8 = 0b1000;
+
+/// Printed image, 160 pixels wide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Printout {
+    pub height: usize,
+    /// Grayscale pixels, 0x00 for black and 0xFF for white.
+    pub pixels: Vec<u8>,
+    /// Paper feed before and after, in the unit defined by the printer.
+    pub margin_before: u8,
+    pub margin_after: u8,
+    /// 0x00 for -25% darkness, 0x7F for +25% darkness.
+    pub exposure: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLo,
+    LengthHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    /// Printer responds with its device ID.
+    Alive,
+    /// Printer responds with its status.
+    Status,
+}
+
+#[derive(Debug)]
+struct Packet {
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    calculated_checksum: u16,
+}
+
+#[derive(Debug)]
+struct Inner {
+    stage: Stage,
+    packet: Packet,
+    /// Image data in 2bpp tiles.
+    buffer: Vec<u8>,
+    status: u8,
+    busy_inquiries: u8,
+    append: bool,
+    printouts: Vec<Printout>,
+}
+
+/// Game Boy Printer. Clones share the same printer, so one can be
+/// attached to [`crate::GameBoy::replace_serial_link`] while the
+/// other is used to collect printouts.
+#[derive(Debug, Clone)]
+pub struct Printer {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                stage: Stage::Magic1,
+                packet: Packet {
+                    command: 0,
+                    compressed: false,
+                    length: 0,
+                    data: vec![],
+                    checksum: 0,
+                    calculated_checksum: 0,
+                },
+                buffer: Vec::with_capacity(BUFFER_CAPACITY),
+                status: 0,
+                busy_inquiries: 0,
+                append: false,
+                printouts: vec![],
+            })),
+        }
+    }
+
+    /// Append each print to the last printout, instead of starting
+    /// a new one. Games print large images in several parts.
+    pub fn set_append(&self, append: bool) {
+        self.inner.lock().unwrap().append = append;
+    }
+
+    pub fn take_printouts(&self) -> Vec<Printout> {
+        std::mem::take(&mut self.inner.lock().unwrap().printouts)
+    }
+}
+
+impl Inner {
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut response = 0x00;
+        let packet = &mut self.packet;
+        match self.stage {
+            Stage::Magic1 => {
+                if byte == 0x88 {
+                    self.stage = Stage::Magic2;
+                }
+            }
+            Stage::Magic2 => {
+                self.stage = if byte == 0x33 { Stage::Command } else { Stage::Magic1 };
+            }
+            Stage::Command => {
+                packet.command = byte;
+                packet.calculated_checksum = byte as u16;
+                self.stage = Stage::Compression;
+            }
+            Stage::Compression => {
+                packet.compressed = byte & 1 != 0;
+                packet.calculated_checksum = packet.calculated_checksum.wrapping_add(byte as u16);
+                self.stage = Stage::LengthLo;
+            }
+            Stage::LengthLo => {
+                packet.length = byte as u16;
+                packet.calculated_checksum = packet.calculated_checksum.wrapping_add(byte as u16);
+                self.stage = Stage::LengthHi;
+            }
+            Stage::LengthHi => {
+                packet.length |= (byte as u16) << 8;
+                packet.calculated_checksum = packet.calculated_checksum.wrapping_add(byte as u16);
+                packet.data.clear();
+                self.stage = if packet.length == 0 { Stage::ChecksumLo } else { Stage::Data };
+            }
+            Stage::Data => {
+                packet.data.push(byte);
+                packet.calculated_checksum = packet.calculated_checksum.wrapping_add(byte as u16);
+                if packet.data.len() == packet.length as usize {
+                    self.stage = Stage::ChecksumLo;
+                }
+            }
+            Stage::ChecksumLo => {
+                packet.checksum = byte as u16;
+                self.stage = Stage::ChecksumHi;
+            }
+            Stage::ChecksumHi => {
+                packet.checksum |= (byte as u16) << 8;
+                self.stage = Stage::Alive;
+            }
+            Stage::Alive => {
+                response = 0x81;
+                self.stage = Stage::Status;
+            }
+            Stage::Status => {
+                self.exec_command();
+                response = self.status;
+                self.stage = Stage::Magic1;
+            }
+        }
+
+        response
+    }
+
+    fn exec_command(&mut self) {
+        if self.packet.checksum != self.packet.calculated_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.packet.command {
+            0x01 => {
+                // Initialize
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_inquiries = 0;
+            }
+            0x02 => {
+                // Print
+                if let [_sheets, margins, palette, exposure] = self.packet.data[..] {
+                    self.print(margins, palette, exposure);
+                }
+            }
+            0x04 => {
+                // Data, an empty packet marks the end of data.
+                let data = std::mem::take(&mut self.packet.data);
+                if self.packet.compressed {
+                    decompress(&data, &mut self.buffer);
+                } else {
+                    self.buffer.extend_from_slice(&data);
+                }
+                self.buffer.truncate(BUFFER_CAPACITY);
+                self.packet.data = data;
+
+                if !self.buffer.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+                if self.buffer.len() == BUFFER_CAPACITY {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            0x0F => {
+                // Status inquiry
+                if self.busy_inquiries > 0 {
+                    self.busy_inquiries -= 1;
+                    if self.busy_inquiries == 0 {
+                        self.status &= !STATUS_PRINTING;
+                    }
+                }
+            }
+            command => log::warn!("Unknown printer command {:#04X}", command),
+        }
+    }
+
+    fn print(&mut self, margins: u8, palette: u8, exposure: u8) {
+        let tile_rows = self.buffer.len() / BYTES_PER_TILE_ROW;
+        let height = tile_rows * 8;
+        let mut pixels = vec![0xFF; PRINTOUT_WIDTH * height];
+
+        for (index, tile) in self.buffer[..tile_rows * BYTES_PER_TILE_ROW].chunks(16).enumerate() {
+            let (tile_y, tile_x) = (index / 20, index % 20);
+            for (line, bytes) in tile.chunks(2).enumerate() {
+                for col in 0..8 {
+                    let bit = 7 - col;
+                    let color_id = (((bytes[1] >> bit) & 1) << 1) | ((bytes[0] >> bit) & 1);
+                    let shade = (palette >> (color_id * 2)) & 0b11;
+                    let y = tile_y * 8 + line;
+                    let x = tile_x * 8 + col;
+                    pixels[y * PRINTOUT_WIDTH + x] = 0xFF - shade * 0x55;
+                }
+            }
+        }
+
+        let margin_before = margins >> 4;
+        let margin_after = margins & 0x0F;
+        match self.printouts.last_mut() {
+            Some(last) if self.append => {
+                last.height += height;
+                last.pixels.extend_from_slice(&pixels);
+                last.margin_after = margin_after;
+            }
+            _ => self.printouts.push(Printout {
+                height,
+                pixels,
+                margin_before,
+                margin_after,
+                exposure: exposure & 0x7F,
+            }),
+        }
+
+        self.buffer.clear();
+        self.status = (self.status & !(STATUS_UNPROCESSED | STATUS_IMAGE_FULL)) | STATUS_PRINTING;
+        self.busy_inquiries = BUSY_INQUIRIES;
+    }
+}
+
+/// Run-length encoding. A control byte with bit 7 set is followed by a
+/// byte repeated `(control & 0x7F) + 2` times, otherwise it's followed by
+/// `control + 1` bytes to copy.
+fn decompress(data: &[u8], out: &mut Vec<u8>) {
+    let mut iter = data.iter().copied();
+    while let Some(control) = iter.next() {
+        if control & 0x80 != 0 {
+            let Some(byte) = iter.next() else { break };
+            let len = (control & 0x7F) as usize + 2;
+            out.extend(std::iter::repeat_n(byte, len));
+        } else {
+            out.extend(iter.by_ref().take(control as usize + 1));
+        }
+    }
+}
+
+impl SerialLink for Printer {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.inner.lock().unwrap().receive(byte)
+    }
+
+    fn poll(&mut self, _byte: u8) -> Option<u8> {
+        // Printer never drives the clock.
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a packet, and return the device ID and status.
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![0x88, 0x33, command, compressed as u8];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        let checksum = packet[2..].iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+        packet.extend_from_slice(&checksum.to_le_bytes());
+
+        for byte in packet {
+            assert_eq!(printer.transfer(byte), 0x00);
+        }
+        (printer.transfer(0x00), printer.transfer(0x00))
+    }
+
+    /// 2 tile rows, with color 3 at the first tile row and color 1 at the second.
+    fn image_data() -> Vec<u8> {
+        let mut data = vec![0xFF; BYTES_PER_TILE_ROW];
+        data.extend([0xFF, 0x00].repeat(BYTES_PER_TILE_ROW / 2));
+        data
+    }
+
+    #[test]
+    fn print_image() {
+        let mut printer = Printer::new();
+        assert_eq!(send(&mut printer, 0x01, false, &[]), (0x81, 0x00));
+        assert_eq!(send(&mut printer, 0x04, false, &image_data()), (0x81, STATUS_UNPROCESSED));
+        assert_eq!(send(&mut printer, 0x04, false, &[]), (0x81, STATUS_UNPROCESSED));
+        // Palette: 3 => black, 1 => light gray.
+        let (_, status) = send(&mut printer, 0x02, false, &[0x01, 0x13, 0xE4, 0x40]);
+        assert_eq!(status, STATUS_PRINTING);
+        assert_eq!(send(&mut printer, 0x0F, false, &[]), (0x81, STATUS_PRINTING));
+        assert_eq!(send(&mut printer, 0x0F, false, &[]), (0x81, 0x00));
+
+        let printouts = printer.take_printouts();
+        assert_eq!(printouts.len(), 1);
+        let printout = &printouts[0];
+        assert_eq!(printout.height, 16);
+        assert_eq!((printout.margin_before, printout.margin_after), (1, 3));
+        assert_eq!(printout.exposure, 0x40);
+        assert_eq!(printout.pixels[0], 0x00);
+        assert_eq!(printout.pixels[PRINTOUT_WIDTH * 16 - 1], 0xAA);
+        assert!(printer.take_printouts().is_empty());
+    }
+
+    #[test]
+    fn compressed_data() {
+        let mut out = vec![];
+        decompress(&[0x81, 0xAB, 0x01, 0x12, 0x34], &mut out);
+        assert_eq!(out, vec![0xAB, 0xAB, 0xAB, 0x12, 0x34]);
+
+        let mut printer = Printer::new();
+        // 320 bytes of 0xFF in runs of 129, 129 and 62 bytes.
+        let mut data = vec![0xFF, 0xFF, 0xFF, 0xFF, 0x80 | 60, 0xFF];
+        // 320 bytes of 0xFF00 copied in 128, 128 and 64 bytes.
+        for len in [128, 128, 64] {
+            data.push(len as u8 - 1);
+            data.extend([0xFF, 0x00].repeat(len / 2));
+        }
+        send(&mut printer, 0x04, true, &data);
+        send(&mut printer, 0x02, false, &[0x01, 0x00, 0xE4, 0x40]);
+
+        let expected = {
+            let mut printer = Printer::new();
+            send(&mut printer, 0x04, false, &image_data());
+            send(&mut printer, 0x02, false, &[0x01, 0x00, 0xE4, 0x40]);
+            printer.take_printouts()
+        };
+        assert_eq!(printer.take_printouts(), expected);
+    }
+
+    #[test]
+    fn append_prints() {
+        let mut printer = Printer::new();
+        printer.set_append(true);
+        for _ in 0..2 {
+            send(&mut printer, 0x04, false, &image_data());
+            send(&mut printer, 0x02, false, &[0x01, 0x00, 0xE4, 0x40]);
+        }
+
+        let printouts = printer.take_printouts();
+        assert_eq!(printouts.len(), 1);
+        assert_eq!(printouts[0].height, 32);
+        assert_eq!(printouts[0].pixels.len(), PRINTOUT_WIDTH * 32);
+    }
+
+    #[test]
+    fn checksum_error() {
+        let mut printer = Printer::new();
+        for byte in [0x88, 0x33, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00] {
+            printer.transfer(byte);
+        }
+        assert_eq!(printer.transfer(0x00), 0x81);
+        assert_eq!(printer.transfer(0x00), STATUS_CHECKSUM_ERROR);
+    }
+}
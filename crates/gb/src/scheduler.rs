@@ -0,0 +1,82 @@
+/// Components are run lazily. Machine cycles are accumulated, and run
+/// when any component is accessed, or the next event is due. Events are
+/// what components do without being accessed, e.g. requesting interrupts.
+/// Cycles before the next event are idle, which can be run in bulk.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Scheduler {
+    /// Machine cycles not run yet.
+    pending: u32,
+    /// Idle machine cycles since components were run.
+    idle: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Run {
+    /// Machine cycles which can be run in bulk.
+    Idle(u32),
+    /// A machine cycle which must be run precisely, after which
+    /// [`Scheduler::reschedule`] is expected.
+    Busy,
+}
+
+impl Scheduler {
+    /// Returns true if components need running.
+    #[inline]
+    pub(crate) fn step(&mut self, m_cycles: u32) -> bool {
+        self.pending += m_cycles;
+        self.pending > self.idle
+    }
+
+    /// Idle machine cycles from now.
+    #[inline]
+    pub(crate) fn idle_cycles(&self) -> u32 {
+        self.idle.saturating_sub(self.pending)
+    }
+
+    /// Set idle machine cycles since components were run.
+    #[inline]
+    pub(crate) fn reschedule(&mut self, idle: u32) {
+        self.idle = idle;
+    }
+
+    /// Take pending machine cycles to run.
+    pub(crate) fn next(&mut self) -> Option<Run> {
+        if self.pending == 0 {
+            return None;
+        }
+
+        if self.idle > 0 {
+            let m_cycles = self.pending.min(self.idle);
+            self.pending -= m_cycles;
+            self.idle -= m_cycles;
+            Some(Run::Idle(m_cycles))
+        } else {
+            self.pending -= 1;
+            Some(Run::Busy)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_idle_cycles_in_bulk() {
+        let mut scheduler = Scheduler::default();
+        scheduler.reschedule(10);
+
+        assert!(!scheduler.step(4));
+        assert!(!scheduler.step(6));
+        assert_eq!(scheduler.idle_cycles(), 0);
+        assert!(scheduler.step(2));
+
+        assert_eq!(scheduler.next(), Some(Run::Idle(10)));
+        assert_eq!(scheduler.next(), Some(Run::Busy));
+        scheduler.reschedule(0);
+        assert_eq!(scheduler.next(), Some(Run::Busy));
+        scheduler.reschedule(3);
+        assert_eq!(scheduler.next(), None);
+        assert_eq!(scheduler.idle_cycles(), 3);
+    }
+}
@@ -1,8 +1,45 @@
-use gb_shared::{Memory, Snapshot};
+use gb_shared::{is_bit_set, Interrupt, InterruptRequest, MachineModel, Memory, Snapshot};
+
+/// The other end of the link cable. Bytes are exchanged as a whole
+/// when the transfer finishes, instead of bit by bit.
+///
+/// @see https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+pub trait SerialLink: Send {
+    /// Called on the side using internal clock when it has shifted out
+    /// `byte`. Return the byte shifted in from the other end.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    /// Called once per machine cycle on the side using external clock
+    /// while it's waiting for the other end, with `byte` to shift out.
+    /// Return the byte shifted in once the other end has clocked a
+    /// whole byte.
+    fn poll(&mut self, byte: u8) -> Option<u8>;
+}
+
+/// Called with every byte sent over serial.
+#[cfg(not(target_family = "wasm"))]
+pub type SerialOutputHandle = dyn FnMut(u8) + Send;
+#[cfg(target_family = "wasm")]
+pub type SerialOutputHandle = dyn FnMut(u8);
 
 pub(crate) struct Serial {
+    /// SB
     data: u8,
+    /// SC
+    /// - Bit 7: Transfer enable.
+    /// - Bit 1: Clock speed(CGB only).
+    /// - Bit 0: Clock select, 1 for internal clock.
     control: u8,
+    /// Bits shifted in the ongoing transfer.
+    shifted_bits: u8,
+    /// The byte being shifted out.
+    out: u8,
+    pub(crate) machine_model: MachineModel,
+    pub(crate) link: Option<Box<dyn SerialLink>>,
+    /// Bytes sent, only captured if enabled.
+    pub(crate) output: Option<Vec<u8>>,
+    pub(crate) output_handle: Option<Box<SerialOutputHandle>>,
+    irq: Interrupt,
 }
 
 impl Memory for Serial {
@@ -10,7 +47,14 @@ impl Memory for Serial {
         if addr == 0xFF01 {
             self.data = value;
         } else if addr == 0xFF02 {
-            self.control = value | 0x7C;
+            self.control = match self.machine_model {
+                MachineModel::DMG => value | 0x7E,
+                MachineModel::CGB => value | 0x7C,
+            };
+            if self.transferring() {
+                self.shifted_bits = 0;
+                self.out = self.data;
+            }
         } else {
             unreachable!()
         }
@@ -27,9 +71,129 @@ impl Memory for Serial {
     }
 }
 
+/// The link and the output handle are not cloned, i.e. the clone is
+/// disconnected.
+impl Clone for Serial {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data,
+            control: self.control,
+            shifted_bits: self.shifted_bits,
+            out: self.out,
+            machine_model: self.machine_model,
+            link: None,
+            output: self.output.clone(),
+            output_handle: None,
+            irq: self.irq.clone(),
+        }
+    }
+}
+
 impl Serial {
-    pub(crate) fn new() -> Self {
-        Self { data: 0, control: 0x7E }
+    pub(crate) fn new(machine_model: MachineModel) -> Self {
+        Self {
+            data: 0,
+            control: 0x7E,
+            shifted_bits: 0,
+            out: 0,
+            machine_model,
+            link: None,
+            output: None,
+            output_handle: None,
+            irq: Interrupt::default(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn transferring(&self) -> bool {
+        is_bit_set!(self.control, 7)
+    }
+
+    #[inline]
+    fn internal_clock(&self) -> bool {
+        is_bit_set!(self.control, 0)
+    }
+
+    /// The bit of DIV whose falling edge shifts a bit, while transferring
+    /// with internal clock.
+    ///
+    /// Internal clock is 8192Hz, or 262144Hz with CGB fast clock, which is
+    /// driven by the falling edge of DIV bit 8, or bit 3 respectively.
+    pub(crate) fn shift_bit(&self) -> Option<u8> {
+        if !self.transferring() || !self.internal_clock() {
+            return None;
+        }
+        let fast = self.machine_model == MachineModel::CGB && is_bit_set!(self.control, 1);
+        Some(if fast { 3 } else { 8 })
+    }
+
+    /// Whether the link is polled, while transferring with external clock.
+    /// Without a link, the transfer waits forever.
+    #[inline]
+    pub(crate) fn polling(&self) -> bool {
+        self.transferring() && !self.internal_clock() && self.link.is_some()
+    }
+
+    /// Called on every clock, after `div` gets increased.
+    pub(crate) fn step(&mut self, div: u16) {
+        if !self.transferring() {
+            return;
+        }
+
+        if !self.internal_clock() {
+            // Nothing clocks the transfer without a link. Otherwise the link
+            // is polled once per machine cycle, finer than the fastest
+            // internal clock of 4 machine cycles per bit.
+            let Some(link) = self.link.as_mut() else {
+                return;
+            };
+            if div & 0x03 != 0 {
+                return;
+            }
+            if let Some(byte) = link.poll(self.data) {
+                let out = self.data;
+                self.data = byte;
+                self.finish(out);
+            }
+            return;
+        }
+
+        let Some(bit) = self.shift_bit() else {
+            return;
+        };
+        // Lower bits up to `bit` are all cleared on its falling edge.
+        if div & ((2 << bit) - 1) != 0 {
+            return;
+        }
+
+        // Shift in 1 as if nothing is connected, and the received byte
+        // replaces it at the end.
+        self.data = (self.data << 1) | 1;
+        self.shifted_bits += 1;
+        if self.shifted_bits == 8 {
+            self.data = match self.link.as_mut() {
+                Some(link) => link.transfer(self.out),
+                None => 0xFF,
+            };
+            self.finish(self.out);
+        }
+    }
+
+    fn finish(&mut self, out: u8) {
+        if let Some(output) = self.output.as_mut() {
+            output.push(out);
+        }
+        if let Some(output_handle) = self.output_handle.as_mut() {
+            output_handle(out);
+        }
+
+        self.control &= 0x7F;
+        self.shifted_bits = 0;
+        self.irq.request_serial();
+    }
+
+    pub(crate) fn take_irq(&mut self) -> u8 {
+        self.irq.take()
     }
 }
 
@@ -37,17 +201,134 @@ impl Serial {
 pub(crate) struct SerialSnapshot {
     data: u8,
     control: u8,
+    shifted_bits: u8,
+    out: u8,
+    irq: u8,
 }
 
 impl Snapshot for Serial {
     type Snapshot = SerialSnapshot;
 
     fn take_snapshot(&self) -> Self::Snapshot {
-        SerialSnapshot { data: self.data, control: self.control }
+        SerialSnapshot {
+            data: self.data,
+            control: self.control,
+            shifted_bits: self.shifted_bits,
+            out: self.out,
+            irq: self.irq.0,
+        }
     }
 
     fn restore_snapshot(&mut self, snapshot: Self::Snapshot) {
         self.data = snapshot.data;
         self.control = snapshot.control;
+        self.shifted_bits = snapshot.shifted_bits;
+        self.out = snapshot.out;
+        self.irq = Interrupt(snapshot.irq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gb_shared::InterruptType;
+
+    /// Step `clocks` clocks with DIV starting from 0.
+    fn run(serial: &mut Serial, div: &mut u16, clocks: u32) {
+        for _ in 0..clocks {
+            *div = div.wrapping_add(1);
+            serial.step(*div);
+        }
+    }
+
+    struct Echo;
+
+    impl SerialLink for Echo {
+        fn transfer(&mut self, byte: u8) -> u8 {
+            !byte
+        }
+
+        fn poll(&mut self, byte: u8) -> Option<u8> {
+            Some(byte.rotate_left(4))
+        }
+    }
+
+    #[test]
+    fn receive_0xff_if_disconnected() {
+        let mut serial = Serial::new(MachineModel::DMG);
+        let mut div = 0;
+        serial.write(0xFF01, 0x42);
+        serial.write(0xFF02, 0x81);
+
+        // 8 bits at 8192Hz.
+        run(&mut serial, &mut div, 512 * 8 - 1);
+        assert_eq!(serial.read(0xFF02), 0xFF);
+        assert_eq!(serial.take_irq(), 0);
+
+        run(&mut serial, &mut div, 1);
+        assert_eq!(serial.read(0xFF01), 0xFF);
+        assert_eq!(serial.read(0xFF02), 0x7F);
+        assert_eq!(serial.take_irq(), InterruptType::Serial as u8);
+    }
+
+    #[test]
+    fn fast_clock_on_cgb_only() {
+        let mut serial = Serial::new(MachineModel::CGB);
+        let mut div = 0;
+        serial.write(0xFF02, 0x83);
+        run(&mut serial, &mut div, 16 * 8);
+        assert_eq!(serial.take_irq(), InterruptType::Serial as u8);
+
+        let mut serial = Serial::new(MachineModel::DMG);
+        let mut div = 0;
+        serial.write(0xFF02, 0x83);
+        run(&mut serial, &mut div, 16 * 8);
+        assert_eq!(serial.take_irq(), 0);
+    }
+
+    #[test]
+    fn external_clock_waits_for_partner() {
+        let mut serial = Serial::new(MachineModel::DMG);
+        let mut div = 0;
+        serial.write(0xFF01, 0x12);
+        serial.write(0xFF02, 0x80);
+        run(&mut serial, &mut div, 0x10000);
+        assert_eq!(serial.read(0xFF02), 0xFE);
+        assert_eq!(serial.take_irq(), 0);
+
+        // Polled once per machine cycle.
+        serial.link = Some(Box::new(Echo));
+        run(&mut serial, &mut div, 3);
+        assert_eq!(serial.take_irq(), 0);
+        run(&mut serial, &mut div, 1);
+        assert_eq!(serial.read(0xFF01), 0x21);
+        assert_eq!(serial.take_irq(), InterruptType::Serial as u8);
+    }
+
+    #[test]
+    fn capture_output() {
+        let mut serial = Serial::new(MachineModel::DMG);
+        serial.output = Some(vec![]);
+        let mut div = 0;
+        for byte in [0x12, 0x34] {
+            serial.write(0xFF01, byte);
+            serial.write(0xFF02, 0x81);
+            run(&mut serial, &mut div, 512 * 8);
+        }
+
+        assert_eq!(serial.output, Some(vec![0x12, 0x34]));
+    }
+
+    #[test]
+    fn exchange_with_link() {
+        let mut serial = Serial::new(MachineModel::DMG);
+        serial.link = Some(Box::new(Echo));
+        let mut div = 0;
+        serial.write(0xFF01, 0x0F);
+        serial.write(0xFF02, 0x81);
+        run(&mut serial, &mut div, 512 * 8);
+
+        assert_eq!(serial.read(0xFF01), 0xF0);
+        assert_eq!(serial.take_irq(), InterruptType::Serial as u8);
     }
 }
@@ -1,4 +1,6 @@
-use gb_shared::{is_bit_set, ByteView, Interrupt, InterruptRequest, Memory, Snapshot};
+use gb_shared::{
+    is_bit_set, ByteView, HardwareModel, Interrupt, InterruptRequest, Memory, Snapshot,
+};
 
 enum CounterIncCycles {
     Cycles1024,
@@ -19,6 +21,23 @@ impl From<u8> for CounterIncCycles {
     }
 }
 
+impl CounterIncCycles {
+    /// The bit of DIV whose falling edge increases TIMA.
+    fn div_bit(&self) -> u8 {
+        match self {
+            CounterIncCycles::Cycles1024 => 9,
+            CounterIncCycles::Cycles16 => 3,
+            CounterIncCycles::Cycles64 => 5,
+            CounterIncCycles::Cycles256 => 7,
+        }
+    }
+}
+
+/// Clock cycles between TIMA overflowing and being reloaded with TMA.
+const RELOAD_DELAY: u8 = 4;
+
+/// @see https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+#[derive(Clone)]
 pub(crate) struct Timer {
     /// Divider
     /// It's increased at a rate of 16384 Hz.
@@ -37,26 +56,40 @@ pub(crate) struct Timer {
     ///     - 10: 65536 Hz(increased by 1 every 64 cycles)
     ///     - 11: 16384 Hz(increased by 1 every 256 cycles)
     tac: u8,
+    /// Clock cycles left before reloading TIMA, after it overflowed.
+    /// TIMA reads 0 in the meantime.
+    overflow: u8,
+    /// Clock cycles left of the machine cycle after reloading TIMA,
+    /// in which writing TIMA is ignored and writing TMA goes to TIMA too.
+    reloaded: u8,
     irq: Interrupt,
 }
 
 impl Memory for Timer {
     fn write(&mut self, addr: u16, value: u8) {
         if addr == 0xFF04 {
-            // Write any value to it will reset it to zero.
+            // Write any value to it will reset it to zero,
+            // which may be a falling edge.
+            let signal = self.signal();
             self.div = 0;
+            self.detect_falling_edge(signal);
         } else if addr == 0xFF05 {
-            self.tima = value;
+            if self.reloaded == 0 {
+                self.tima = value;
+                // Cancel the pending reload.
+                self.overflow = 0;
+            }
         } else if addr == 0xFF06 {
             self.tma = value;
-        } else if addr == 0xFF07 {
-            let value = value | 0xF8;
-            if (self.tac & 0b11) != (value & 0b11) {
-                // Clock select changes
-                self.div = 0;
-                self.tima = self.tma;
+            if self.reloaded > 0 {
+                self.tima = value;
             }
-            self.tac = value;
+        } else if addr == 0xFF07 {
+            // Disabling the timer, or switching to a bit which is unset,
+            // may be a falling edge.
+            let signal = self.signal();
+            self.tac = value | 0xF8;
+            self.detect_falling_edge(signal);
         } else {
             unreachable!()
         }
@@ -78,32 +111,98 @@ impl Memory for Timer {
 }
 
 impl Timer {
-    pub fn new() -> Self {
-        Self { div: 0xAB00, tima: 0, tma: 0, tac: 0xF8, irq: Interrupt::default() }
+    /// DIV left by the boot ROM of `model`. It's unknown on SGB and CGB,
+    /// where it depends on how long the boot ROM takes, and DMG's is used.
+    /// @see https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+    pub fn new(model: HardwareModel) -> Self {
+        let div = match model {
+            HardwareModel::DMG0 => 0x1800,
+            _ => 0xAB00,
+        };
+        Self {
+            div,
+            tima: 0,
+            tma: 0,
+            tac: 0xF8,
+            overflow: 0,
+            reloaded: 0,
+            irq: Interrupt::default(),
+        }
     }
 
-    pub fn step(&mut self) {
-        self.div = self.div.wrapping_add(1);
-
-        let inc_tima = match CounterIncCycles::from(self.tac) {
-            CounterIncCycles::Cycles1024 => (self.div & 1023) == 0,
-            CounterIncCycles::Cycles16 => (self.div & 15) == 0,
-            CounterIncCycles::Cycles64 => (self.div & 63) == 0,
-            CounterIncCycles::Cycles256 => (self.div & 255) == 0,
-        };
+    /// The selected bit of DIV ANDed with the enable bit of TAC.
+    /// TIMA is increased on its falling edge.
+    fn signal(&self) -> bool {
+        let bit = CounterIncCycles::from(self.tac).div_bit();
+        is_bit_set!(self.tac, 2) && is_bit_set!(self.div, bit)
+    }
 
-        if is_bit_set!(self.tac, 2) && inc_tima {
+    fn detect_falling_edge(&mut self, signal: bool) {
+        if signal && !self.signal() {
             self.tima = self.tima.wrapping_add(1);
             if self.tima == 0 {
+                self.overflow = RELOAD_DELAY;
+            }
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.reloaded = self.reloaded.saturating_sub(1);
+
+        if self.overflow > 0 {
+            self.overflow -= 1;
+            if self.overflow == 0 {
                 self.tima = self.tma;
+                self.reloaded = RELOAD_DELAY;
                 self.irq.request_timer();
             }
         }
+
+        let signal = self.signal();
+        self.div = self.div.wrapping_add(1);
+        self.detect_falling_edge(signal);
+    }
+
+    /// Clock cycles from now until the `nth` falling edge of `bit` of DIV.
+    pub(crate) fn clocks_until_falling_edge(&self, bit: u8, nth: u32) -> u32 {
+        let period = 1u32 << (bit + 1);
+        period - (self.div as u32 % period) + (nth - 1) * period
+    }
+
+    /// Clock cycles from now in which no interrupt can be requested.
+    pub(crate) fn idle_clocks(&self) -> u32 {
+        if self.overflow > 0 || self.reloaded > 0 {
+            return 0;
+        }
+        if !is_bit_set!(self.tac, 2) {
+            return u32::MAX;
+        }
+
+        let bit = CounterIncCycles::from(self.tac).div_bit();
+        self.clocks_until_falling_edge(bit, 256 - self.tima as u32) - 1
+    }
+
+    /// Run `clocks` which are idle, see [`Timer::idle_clocks`].
+    pub(crate) fn step_idle(&mut self, clocks: u32) {
+        debug_assert!(clocks <= self.idle_clocks());
+
+        let div = self.div as u32;
+        if is_bit_set!(self.tac, 2) {
+            let bit = CounterIncCycles::from(self.tac).div_bit() + 1;
+            let falling_edges = ((div + clocks) >> bit) - (div >> bit);
+            self.tima += falling_edges as u8;
+        }
+        self.div = (div + clocks) as u16;
     }
 
     pub fn take_irq(&mut self) -> u8 {
         self.irq.take()
     }
+
+    #[inline]
+    pub(crate) fn div(&self) -> u16 {
+        self.div
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -112,6 +211,8 @@ pub(crate) struct TimerSnapshot {
     tima: u8,
     tma: u8,
     tac: u8,
+    overflow: u8,
+    reloaded: u8,
     irq: u8,
 }
 
@@ -124,6 +225,8 @@ impl Snapshot for Timer {
             tima: self.tima,
             tma: self.tma,
             tac: self.tac,
+            overflow: self.overflow,
+            reloaded: self.reloaded,
             irq: self.irq.0,
         }
     }
@@ -133,6 +236,8 @@ impl Snapshot for Timer {
         self.tima = snapshot.tima;
         self.tma = snapshot.tma;
         self.tac = snapshot.tac;
+        self.overflow = snapshot.overflow;
+        self.reloaded = snapshot.reloaded;
         self.irq.0 = snapshot.irq;
     }
 }
@@ -155,7 +260,7 @@ mod tests {
     }
 
     fn prepare_timer() -> Timer {
-        let mut timer = Timer::new();
+        let mut timer = Timer::new(HardwareModel::DMG);
         // Reset DIV to zero
         timer.write(0xFF04, 0b01);
         timer.write(0xFF04, 0b00);
@@ -163,6 +268,13 @@ mod tests {
         timer
     }
 
+    #[test]
+    fn post_boot_div() {
+        assert_eq!(Timer::new(HardwareModel::DMG0).read(0xFF04), 0x18);
+        assert_eq!(Timer::new(HardwareModel::DMG).read(0xFF04), 0xAB);
+        assert_eq!(Timer::new(HardwareModel::MGB).read(0xFF04), 0xAB);
+    }
+
     #[test]
     fn div_increased_ignoring_tac() {
         let mut timer = prepare_timer();
@@ -279,7 +391,7 @@ mod tests {
 
     #[test]
     fn request_timer_interrupt() {
-        let mut timer = Timer::new();
+        let mut timer = Timer::new(HardwareModel::DMG);
         write_tac(&mut timer, 0b111);
 
         write_tma(&mut timer, 0xFE);
@@ -289,14 +401,20 @@ mod tests {
             timer.step();
         }
         timer.step();
+        // TIMA reads 0 for a machine cycle before reloading.
+        assert_eq!(timer.take_irq(), 0);
+        assert_eq!(timer.tima, 0);
 
+        for _ in 0..4 {
+            timer.step();
+        }
         assert_eq!(timer.take_irq(), InterruptType::Timer as u8);
         assert_eq!(timer.tima, 0xFE);
     }
 
     #[test]
     fn cycles_overflow() {
-        let mut timer = Timer::new();
+        let mut timer = prepare_timer();
         write_tac(&mut timer, 0b111);
 
         for _ in 0..0xFFFF {
@@ -306,25 +424,147 @@ mod tests {
         assert_eq!(timer.read(0xFF04), 255);
 
         timer.step();
-        assert_eq!(timer.take_irq(), InterruptType::Timer as u8);
-        assert_eq!(timer.div, 0);
         assert_eq!(timer.div, 0);
+
+        for _ in 0..4 {
+            timer.step();
+        }
+        assert_eq!(timer.take_irq(), InterruptType::Timer as u8);
     }
 
     #[test]
-    fn reset_on_timer_freq_change() {
+    fn increase_on_falling_edge_of_writing() {
         let mut timer = prepare_timer();
         write_tac(&mut timer, 0b101);
 
-        for _ in 0..16 {
+        // Bit 3 of DIV is set.
+        for _ in 0..8 {
             timer.step();
         }
-        assert_eq!(timer.div, 16);
+        assert_eq!(timer.tima, 0);
+
+        // Resetting DIV.
+        timer.write(0xFF04, 0);
         assert_eq!(timer.tima, 1);
 
+        for _ in 0..8 {
+            timer.step();
+        }
+        // Switching to bit 5, which is unset.
         write_tac(&mut timer, 0b110);
+        assert_eq!(timer.div, 8);
+        assert_eq!(timer.tima, 2);
 
-        assert_eq!(timer.div, 0);
+        for _ in 0..32 {
+            timer.step();
+        }
+        // Disabling the timer.
+        write_tac(&mut timer, 0b010);
+        assert_eq!(timer.tima, 3);
+    }
+
+    fn overflow() -> Timer {
+        let mut timer = prepare_timer();
+        write_tac(&mut timer, 0b101);
+        write_tma(&mut timer, 0x42);
+        write_tima(&mut timer, 0xFF);
+        for _ in 0..16 {
+            timer.step();
+        }
+        assert_eq!(timer.tima, 0);
+
+        timer
+    }
+
+    #[test]
+    fn write_tima_cancels_reload() {
+        let mut timer = overflow();
+        write_tima(&mut timer, 0x10);
+
+        for _ in 0..4 {
+            timer.step();
+        }
+        assert_eq!(timer.take_irq(), 0);
+        assert_eq!(timer.tima, 0x10);
+    }
+
+    #[test]
+    fn write_while_reloading() {
+        let mut timer = overflow();
+        for _ in 0..4 {
+            timer.step();
+        }
+        assert_eq!(timer.take_irq(), InterruptType::Timer as u8);
+        assert_eq!(timer.tima, 0x42);
+
+        write_tima(&mut timer, 0x10);
+        assert_eq!(timer.tima, 0x42);
+        write_tma(&mut timer, 0x24);
+        assert_eq!(timer.tima, 0x24);
+
+        for _ in 0..4 {
+            timer.step();
+        }
+        write_tima(&mut timer, 0x10);
+        write_tma(&mut timer, 0x42);
+        assert_eq!(timer.tima, 0x10);
+    }
+
+    #[test]
+    fn reload_tma_written_while_overflowing() {
+        let mut timer = overflow();
+        write_tma(&mut timer, 0x24);
+
+        for _ in 0..4 {
+            timer.step();
+        }
+        assert_eq!(timer.take_irq(), InterruptType::Timer as u8);
+        assert_eq!(timer.tima, 0x24);
+    }
+
+    #[test]
+    fn increase_on_toggling_rapidly() {
+        let mut timer = prepare_timer();
+        write_tac(&mut timer, 0b100);
+        // Bit 9 of DIV is set.
+        for _ in 0..512 {
+            timer.step();
+        }
         assert_eq!(timer.tima, 0);
+
+        // Only disabling the timer is a falling edge.
+        for tima in 1..=3 {
+            write_tac(&mut timer, 0b000);
+            assert_eq!(timer.tima, tima);
+            write_tac(&mut timer, 0b100);
+            assert_eq!(timer.tima, tima);
+        }
+    }
+
+    #[test]
+    fn step_idle() {
+        for tac in [0b001, 0b100, 0b101, 0b110, 0b111] {
+            let mut stepped = prepare_timer();
+            write_tac(&mut stepped, tac);
+            write_tima(&mut stepped, 0x80);
+            for _ in 0..1234 {
+                stepped.step();
+            }
+            let mut skipped = prepare_timer();
+            write_tac(&mut skipped, tac);
+            write_tima(&mut skipped, 0x80);
+            skipped.step_idle(1234);
+            assert_eq!((skipped.div, skipped.tima), (stepped.div, stepped.tima));
+
+            if tac & 0b100 == 0 {
+                assert_eq!(stepped.idle_clocks(), u32::MAX);
+                continue;
+            }
+            let idle = stepped.idle_clocks();
+            stepped.step_idle(idle);
+            assert_eq!(stepped.tima, 0xFF);
+            stepped.step();
+            assert_eq!(stepped.tima, 0x00);
+        }
     }
 }
@@ -48,6 +48,13 @@ impl Vdma {
 }
 
 impl Vdma {
+    /// Whether a transfer is started and not finished yet, which may
+    /// be waiting for HBlank.
+    #[inline]
+    pub(crate) fn in_progress(&self) -> bool {
+        self.active && !self.terminated
+    }
+
     pub(crate) fn active(&self, ly: u8, hblank: bool) -> bool {
         let mut active = self.active && !self.terminated;
         if self.hdma {
@@ -1,6 +1,6 @@
-use gb_shared::{MachineModel, Memory, Snapshot};
+use gb_shared::{HardwareModel, MachineModel, Memory, Snapshot};
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub(crate) struct WorkRam {
     /// In DMG, there're 2 banks in used.
     /// In CGB, there're 8 banks in used, and 1-7 is switchable.
@@ -10,14 +10,28 @@ pub(crate) struct WorkRam {
 }
 
 impl WorkRam {
-    pub(crate) fn new(machine_model: MachineModel) -> Self {
-        Self {
-            ram: match machine_model {
-                MachineModel::DMG => vec![0; 0x4000],
-                MachineModel::CGB => vec![0; 0x8000],
-            },
-            bank_num: 0,
-        }
+    pub(crate) fn new(model: HardwareModel, machine_model: MachineModel) -> Self {
+        let mut ram = match machine_model {
+            MachineModel::DMG => vec![0; 0x4000],
+            MachineModel::CGB => vec![0; 0x8000],
+        };
+        fill_power_on_garbage(&mut ram, model);
+
+        Self { ram, bank_num: 0 }
+    }
+}
+
+/// RAM is not cleared at power on, and some games use the garbage in it
+/// as random seed. Fill it with a fixed pseudo-random pattern per model
+/// instead, to keep runs reproducible.
+pub(crate) fn fill_power_on_garbage(ram: &mut [u8], model: HardwareModel) {
+    let mut state = 0x9E37_79B9u32 ^ (model as u32 + 1);
+    for byte in ram {
+        // xorshift32
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        *byte = state as u8;
     }
 }
 
@@ -25,6 +39,22 @@ impl WorkRam {
     fn bank_num(&self) -> u8 {
         self.bank_num.max(1)
     }
+
+    #[inline]
+    pub(crate) fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    #[inline]
+    pub(crate) fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Offset in `ram` of the bank mapped at [0xD000, 0xDFFF].
+    #[inline]
+    pub(crate) fn bank_offset(&self) -> usize {
+        0x1000 * self.bank_num() as usize
+    }
 }
 
 impl Memory for WorkRam {
@@ -0,0 +1,280 @@
+//! Run the test ROMs under `roms` headlessly, and print a pass/fail matrix.
+//!
+//! Only ROMs committed to the tree are run by default. The others, e.g.
+//! those in the submodule, and ROMs with reference images, are run by
+//! `cargo test -p gb --test conformance -- --ignored` once checked out.
+//! Mooneye Test Suite is not bundled, and its ROMs are looked up under the
+//! `build` directory of the suite that `GB_MOONEYE_DIR` points to, which is
+//! built by `make` with WLA-DX.
+
+use gb::{Cartridge, GameBoy, Manifest, TraceMode, TraceRecord};
+use gb_shared::CPU_FREQ;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const CLOCKS_PER_FRAME: u32 = 70224;
+
+enum Expect {
+    /// Blargg's ROMs print the result over serial.
+    Serial,
+    /// Mooneye's ROMs execute `LD B,B` when finished, with B,C,D,E,H,L
+    /// set to the Fibonacci numbers 3,5,8,13,21,34 if passed.
+    Mooneye,
+    /// The frame after running the frames matches the reference image
+    /// shipped with the ROM, which is under `roms` as well.
+    Reference(&'static str),
+    /// FNV-1a hash of the frame after running the frames, which is
+    /// recorded from this emulator as a regression check for ROMs without
+    /// a reference image.
+    FrameHash(u64),
+}
+
+struct Case {
+    path: &'static str,
+    /// Max seconds to run, or the frames to run for [`Expect::FrameHash`].
+    budget: u32,
+    expect: Expect,
+}
+
+/// Cases whose ROMs are committed, along with what they expect.
+const BUNDLED: &[Case] = &[
+    Case { path: "bg_oam_priority.gbc", budget: 60, expect: Expect::FrameHash(0x1DD5568D5228B0E5) },
+    Case {
+        path: "oam_internal_priority.gbc",
+        budget: 60,
+        expect: Expect::FrameHash(0xB2050B74467AC0A1),
+    },
+];
+
+/// Cases which require files not committed, i.e. reference images and
+/// ROMs in the submodule.
+const EXTERNAL: &[Case] = &[
+    // `img/reference-dmg.png` of https://github.com/mattcurrie/dmg-acid2
+    Case { path: "dmg-acid2.gb", budget: 60, expect: Expect::Reference("dmg-acid2.png") },
+    // `img/reference.png` of https://github.com/mattcurrie/cgb-acid2
+    Case { path: "cgb-acid2.gbc", budget: 60, expect: Expect::Reference("cgb-acid2.png") },
+    Case { path: "gb-test-roms/cpu_instrs/cpu_instrs.gb", budget: 120, expect: Expect::Serial },
+    Case { path: "gb-test-roms/instr_timing/instr_timing.gb", budget: 10, expect: Expect::Serial },
+    Case { path: "gb-test-roms/halt_bug.gb", budget: 10, expect: Expect::Serial },
+];
+
+/// Cases of Mooneye Test Suite, whose paths are relative to its `build`.
+const MOONEYE: &[Case] = &[
+    Case { path: "acceptance/instr/daa.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/div_write.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tima_reload.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/rapid_toggle.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tim00.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tim00_div_trigger.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tim01.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tim01_div_trigger.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tim10.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tim10_div_trigger.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tim11.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tim11_div_trigger.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tima_write_reloading.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/timer/tma_write_reloading.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/oam_dma_start.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/oam_dma_restart.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/oam_dma_timing.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/oam_dma/basic.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/oam_dma/reg_read.gb", budget: 10, expect: Expect::Mooneye },
+    Case { path: "acceptance/oam_dma/sources-GS.gb", budget: 10, expect: Expect::Mooneye },
+];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF29CE484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001B3))
+}
+
+fn run_serial(gb: &mut GameBoy, seconds: u32) -> Outcome {
+    gb.capture_serial_output(true);
+
+    let mut output = vec![];
+    for _ in 0..seconds {
+        gb.continue_clocks(CPU_FREQ);
+        output.extend(gb.take_serial_output());
+
+        let text = String::from_utf8_lossy(&output);
+        if text.contains("Passed") {
+            return Outcome::Passed;
+        }
+        if text.contains("Failed") {
+            return Outcome::Failed(text.into_owned());
+        }
+    }
+
+    Outcome::Failed(format!("Timeout: {}", String::from_utf8_lossy(&output)))
+}
+
+fn run_mooneye(gb: &mut GameBoy, seconds: u32) -> Outcome {
+    let finished = Arc::new(Mutex::new(None));
+    let finished_clone = finished.clone();
+    gb.replace_trace_sink(
+        Some(Box::new(move |record: &TraceRecord| {
+            // LD B,B
+            if record.pcmem[0] == 0x40 {
+                finished_clone.lock().unwrap().get_or_insert_with(|| {
+                    [record.b, record.c, record.d, record.e, record.h, record.l]
+                });
+            }
+        })),
+        TraceMode::Doctor,
+    );
+
+    for _ in 0..seconds * 60 {
+        gb.continue_clocks(CLOCKS_PER_FRAME);
+        if let Some(regs) = *finished.lock().unwrap() {
+            return if regs == [3, 5, 8, 13, 21, 34] {
+                Outcome::Passed
+            } else {
+                Outcome::Failed(format!("{:?}", regs))
+            };
+        }
+    }
+
+    Outcome::Failed("Timeout".to_string())
+}
+
+/// The frame in RGB after running `frames`.
+fn run_frames(gb: &mut GameBoy, frames: u32) -> Vec<u8> {
+    let frame = Arc::new(Mutex::new(vec![]));
+    let frame_clone = frame.clone();
+    gb.replace_frame_handle(Some(Box::new(move |data| {
+        *frame_clone.lock().unwrap() = data.to_vec();
+    })));
+
+    for _ in 0..frames {
+        gb.continue_clocks(CLOCKS_PER_FRAME);
+    }
+
+    let frame = frame.lock().unwrap().clone();
+    frame
+}
+
+/// Decode the PNG at `path` into RGB.
+fn read_reference(path: &Path) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(std::fs::File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let rgb = match info.color_type {
+        png::ColorType::Rgb => buf,
+        png::ColorType::Rgba => buf.chunks(4).flat_map(|px| [px[0], px[1], px[2]]).collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks(2).flat_map(|px| [px[0]; 3]).collect(),
+        png::ColorType::Indexed => anyhow::bail!("Palette is not expanded"),
+    };
+
+    Ok((info.width, info.height, rgb))
+}
+
+fn compare_reference(frame: &[u8], reference: &Path) -> Outcome {
+    let (width, height, expected) = match read_reference(reference) {
+        Ok(image) => image,
+        Err(err) => return Outcome::Failed(format!("{}: {}", reference.display(), err)),
+    };
+    if (width, height) != (160, 144) {
+        return Outcome::Failed(format!("Reference image is {}x{}", width, height));
+    }
+
+    let diffs = frame
+        .chunks(3)
+        .zip(expected.chunks(3))
+        .enumerate()
+        .filter(|(_, (actual, expected))| actual != expected)
+        .map(|(nth, _)| (nth % 160, nth / 160))
+        .collect::<Vec<_>>();
+    match diffs.first() {
+        None => Outcome::Passed,
+        Some((x, y)) => {
+            Outcome::Failed(format!("{} pixels differ, first at ({}, {})", diffs.len(), x, y))
+        }
+    }
+}
+
+/// ROMs and reference images are looked up under `roms`.
+fn roms_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../roms")
+}
+
+fn missing(path: &Path) -> Outcome {
+    Outcome::Failed(format!("Missing {}", path.display()))
+}
+
+fn run(case: &Case, dir: &Path) -> Outcome {
+    let path = dir.join(case.path);
+    let Ok(rom) = std::fs::read(&path) else {
+        return missing(&path);
+    };
+
+    let cart = match Cartridge::try_from(rom) {
+        Ok(cart) => cart,
+        Err(err) => return Outcome::Failed(err.to_string()),
+    };
+    let mut gb = GameBoy::new(Manifest::new(cart));
+
+    match case.expect {
+        Expect::Serial => run_serial(&mut gb, case.budget),
+        Expect::Mooneye => run_mooneye(&mut gb, case.budget),
+        Expect::Reference(reference) => {
+            let reference = roms_dir().join(reference);
+            if !reference.exists() {
+                return missing(&reference);
+            }
+            compare_reference(&run_frames(&mut gb, case.budget), &reference)
+        }
+        Expect::FrameHash(expected) => match fnv1a(&run_frames(&mut gb, case.budget)) {
+            hash if hash == expected => Outcome::Passed,
+            hash => Outcome::Failed(format!("Frame hash {:016X}", hash)),
+        },
+    }
+}
+
+/// Run `cases` whose ROMs are under `dir`.
+fn run_cases(cases: &[Case], dir: &Path) {
+    let results = cases.iter().map(|case| (case.path, run(case, dir))).collect::<Vec<_>>();
+
+    for (path, outcome) in &results {
+        let status = match outcome {
+            Outcome::Passed => "PASS",
+            Outcome::Failed(_) => "FAIL",
+        };
+        println!("{:<4} {}", status, path);
+    }
+
+    let failed = results
+        .iter()
+        .filter_map(|(path, outcome)| match outcome {
+            Outcome::Failed(reason) => Some(format!("{}: {}", path, reason)),
+            Outcome::Passed => None,
+        })
+        .collect::<Vec<_>>();
+    assert!(failed.is_empty(), "{:#?}", failed);
+}
+
+#[test]
+fn conformance() {
+    run_cases(BUNDLED, &roms_dir());
+}
+
+#[test]
+#[ignore = "requires gb-test-roms and reference images to be checked out"]
+fn conformance_external() {
+    run_cases(EXTERNAL, &roms_dir());
+}
+
+#[test]
+#[ignore = "requires Mooneye Test Suite to be built, see GB_MOONEYE_DIR"]
+fn mooneye() {
+    let dir = std::env::var_os("GB_MOONEYE_DIR").expect("GB_MOONEYE_DIR is not set");
+    run_cases(MOONEYE, &Path::new(&dir).join("build"));
+}
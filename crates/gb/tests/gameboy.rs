@@ -0,0 +1,709 @@
+//! Whole-system behavior of [`gb::GameBoy`], running programs built into
+//! cartridges. Components are tested by the unit tests of their modules.
+
+mod common {
+    use gb::{BootRom, Cartridge, GameBoy, Manifest};
+
+    pub const CLOCKS_PER_FRAME: u32 = 70224;
+
+    /// Build a 32KiB ROM only cartridge, whose program starts at 0x0150.
+    pub fn build_rom(code: &[u8]) -> Vec<u8> {
+        build_rom_with(code, |_| {})
+    }
+
+    /// Like [`build_rom`], but the header can be customized before
+    /// checksum is calculated.
+    pub fn build_rom_with(code: &[u8], customize: impl FnOnce(&mut [u8])) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // NOP; JP 0x0150
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x0134..0x0134 + 4].copy_from_slice(b"TEST");
+        rom[0x0150..0x0150 + code.len()].copy_from_slice(code);
+        customize(&mut rom);
+
+        rom[0x014D] = rom[0x0134..0x014D]
+            .iter()
+            .fold(0u8, |checksum, v| checksum.wrapping_sub(v.wrapping_add(1)));
+
+        rom
+    }
+
+    pub fn boot(code: &[u8]) -> GameBoy {
+        let cart = Cartridge::try_from(build_rom(code)).unwrap();
+        GameBoy::new(Manifest::new(cart))
+    }
+
+    pub fn boot_with_boot_rom(code: &[u8], boot_rom: Vec<u8>) -> GameBoy {
+        let cart = Cartridge::try_from(build_rom(code)).unwrap();
+        let boot_rom = BootRom::try_from(boot_rom).unwrap();
+        GameBoy::new(Manifest::new(cart).with_boot_rom(boot_rom))
+    }
+}
+
+mod debugger {
+    use super::common;
+    use gb::{Access, BreakReason, Breakpoint, Registers, Watchpoint};
+    use gb_shared::{InterruptType, CPU_FREQ};
+
+    const CODE: &[u8] = &[
+        0x3E, 0x42, // 0x0150: LD A,0x42
+        0xEA, 0x00, 0xC0, // 0x0152: LD (0xC000),A
+        0xFA, 0x00, 0xC0, // 0x0155: LD A,(0xC000)
+        0x18, 0xFE, // 0x0158: JR -2
+    ];
+
+    #[test]
+    fn break_on_breakpoint() {
+        let mut gb = common::boot(CODE);
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0155));
+
+        let reason = gb.run_until_break(CPU_FREQ);
+        assert_eq!(reason, BreakReason::Breakpoint { addr: 0x0155, bank: 0 });
+        assert_eq!(gb.registers().pc, 0x0155);
+        assert_eq!(gb.registers().a, 0x42);
+
+        // Continue from the breakpoint.
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0158));
+        let reason = gb.run_until_break(CPU_FREQ);
+        assert_eq!(reason, BreakReason::Breakpoint { addr: 0x0158, bank: 0 });
+
+        // Break on every iteration of the loop.
+        let reason = gb.run_until_break(CPU_FREQ);
+        assert_eq!(reason, BreakReason::Breakpoint { addr: 0x0158, bank: 0 });
+    }
+
+    #[test]
+    fn break_on_watchpoint() {
+        let mut gb = common::boot(CODE);
+        gb.debugger_mut().add_watchpoint(Watchpoint::write(0xC000..=0xC0FF));
+
+        let reason = gb.run_until_break(CPU_FREQ);
+        assert_eq!(
+            reason,
+            BreakReason::Watchpoint { addr: 0xC000, value: 0x42, access: Access::Write }
+        );
+        assert_eq!(gb.registers().pc, 0x0155);
+
+        gb.debugger_mut().add_watchpoint(Watchpoint::read(0xC000..=0xC000));
+        let reason = gb.run_until_break(CPU_FREQ);
+        assert_eq!(
+            reason,
+            BreakReason::Watchpoint { addr: 0xC000, value: 0x42, access: Access::Read }
+        );
+        assert_eq!(gb.registers().pc, 0x0158);
+    }
+
+    #[test]
+    fn break_on_interrupt() {
+        let mut gb = common::boot(&[
+            0x3E, 0x01, // LD A,0x01
+            0xE0, 0xFF, // LDH (0xFF),A
+            0xFB, // EI
+            0x18, 0xFE, // JR -2
+        ]);
+        gb.debugger_mut().set_break_on_interrupt(true);
+
+        let reason = gb.run_until_break(CPU_FREQ);
+        assert_eq!(reason, BreakReason::Interrupt(InterruptType::VBlank));
+        assert_eq!(gb.registers().pc, 0x0040);
+        assert!(!gb.registers().ime);
+    }
+
+    #[test]
+    fn run_out_of_clocks() {
+        let mut gb = common::boot(CODE);
+
+        assert_eq!(gb.run_until_break(common::CLOCKS_PER_FRAME), BreakReason::ClocksExhausted);
+    }
+
+    #[test]
+    fn modify_registers() {
+        let mut gb = common::boot(CODE);
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0158));
+
+        // Skip the first instruction, and store 0x99 instead.
+        let regs = gb.registers();
+        gb.set_registers(Registers { pc: 0x0152, a: 0x99, sp: 0xDFFF, ..regs });
+
+        assert_eq!(gb.run_until_break(CPU_FREQ), BreakReason::Breakpoint { addr: 0x0158, bank: 0 });
+        assert_eq!(gb.registers().a, 0x99);
+        assert_eq!(gb.registers().sp, 0xDFFF);
+        assert_eq!(gb.peek(0xC000), 0x99);
+    }
+
+    #[test]
+    fn modify_halted() {
+        let mut gb = common::boot(&[
+            0xAF, // 0x0150: XOR A
+            0xE0, 0xFF, // 0x0151: LDH (0xFF),A
+            0x76, // 0x0153: HALT
+            0x06, 0x42, // 0x0154: LD B,0x42
+            0x18, 0xFE, // 0x0156: JR -2
+        ]);
+
+        // Halted forever, as no interrupt is enabled.
+        gb.continue_clocks(common::CLOCKS_PER_FRAME);
+        let regs = gb.registers();
+        assert!(regs.halted);
+        assert_eq!(regs.pc, 0x0154);
+
+        gb.set_registers(Registers { halted: false, ..regs });
+        gb.continue_clocks(common::CLOCKS_PER_FRAME);
+        let regs = gb.registers();
+        assert!(!regs.halted);
+        assert_eq!(regs.b, 0x42);
+
+        gb.set_registers(Registers { halted: true, ..regs });
+        let pc = regs.pc;
+        gb.continue_clocks(common::CLOCKS_PER_FRAME);
+        assert!(gb.registers().halted);
+        assert_eq!(gb.registers().pc, pc);
+    }
+
+    #[test]
+    fn view_ly_after_break() {
+        let mut gb = common::boot(&[
+            0xF0, 0x44, // 0x0150: LDH A,(0x44)
+            0x47, // 0x0152: LD B,A
+            0xF0, 0x04, // 0x0153: LDH A,(0x04)
+            0x4F, // 0x0155: LD C,A
+            0x16, 0x00, // 0x0156: LD D,0x00
+            0x15, // 0x0158: DEC D
+            0x20, 0xFD, // 0x0159: JR NZ,-3
+            0x18, 0xFE, // 0x015B: JR -2
+        ]);
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x015B));
+        assert_eq!(gb.run_until_break(CPU_FREQ), BreakReason::Breakpoint { addr: 0x015B, bank: 0 });
+
+        // About 4096 clocks have passed since LY and DIV were read.
+        let regs = gb.registers();
+        let ly = (gb.peek(0xFF44) as u16 + 154 - regs.b as u16) % 154;
+        assert!((8..=9).contains(&ly), "LY: +{}", ly);
+        let div = gb.peek(0xFF04).wrapping_sub(regs.c);
+        assert!((15..=16).contains(&div), "DIV: +{}", div);
+    }
+
+    #[test]
+    fn view_timer_up_to_date() {
+        let mut gb = common::boot(&[
+            0xF0, 0x44, // 0x0150: LDH A,(0x44)
+            0xFE, 0x90, // 0x0152: CP 0x90
+            0x20, 0xFA, // 0x0154: JR NZ,-6
+            0xAF, // 0x0156: XOR A
+            0xE0, 0x40, // 0x0157: LDH (0x40),A
+            0xE0, 0x05, // 0x0159: LDH (0x05),A
+            0x3E, 0x04, // 0x015B: LD A,0x04
+            0xE0, 0x07, // 0x015D: LDH (0x07),A
+            0xF0, 0x04, // 0x015F: LDH A,(0x04)
+            0x47, // 0x0161: LD B,A
+            0x16, 0x00, // 0x0162: LD D,0x00
+            0x15, // 0x0164: DEC D
+            0x20, 0xFD, // 0x0165: JR NZ,-3
+            0x18, 0xFE, // 0x0167: JR -2
+        ]);
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0167));
+        assert_eq!(gb.run_until_break(CPU_FREQ), BreakReason::Breakpoint { addr: 0x0167, bank: 0 });
+
+        // Nothing happens with LCD off, while about 4096 clocks have passed.
+        let div = gb.peek(0xFF04).wrapping_sub(gb.registers().b);
+        assert!((15..=16).contains(&div), "DIV: +{}", div);
+        assert_eq!(gb.peek(0xFF05), 4);
+    }
+}
+
+mod trace {
+    use super::common;
+    use gb::{BreakReason, Breakpoint, RingBuffer, TraceMode, Watchpoint};
+
+    #[test]
+    fn trace_in_doctor_format() {
+        // LD A,0x42; JR -2
+        let mut gb = common::boot(&[0x3E, 0x42, 0x18, 0xFE]);
+        let buffer = RingBuffer::new(8);
+        gb.replace_trace_sink(Some(Box::new(buffer.clone())), TraceMode::Doctor);
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0152));
+
+        let reason = gb.run_until_break(1000);
+        assert_eq!(reason, BreakReason::Breakpoint { addr: 0x0152, bank: 0 });
+
+        let lines = buffer.records().iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01",
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00",
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:3E,42,18,FE",
+                "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:18,FE,00,00",
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_without_hitting_watchpoints() {
+        // LD A,0x42; JR -2
+        let mut gb = common::boot(&[0x3E, 0x42, 0x18, 0xFE]);
+        let buffer = RingBuffer::new(8);
+        gb.replace_trace_sink(Some(Box::new(buffer.clone())), TraceMode::Rich);
+        // Bytes after JR are in PCMEM only.
+        gb.debugger_mut().add_watchpoint(Watchpoint::read(0x0154..=0x0155));
+
+        assert_eq!(gb.run_until_break(common::CLOCKS_PER_FRAME), BreakReason::ClocksExhausted);
+        assert!(!buffer.records().is_empty());
+    }
+}
+
+mod stop {
+    use super::common;
+    use gb::{BreakReason, Breakpoint};
+    use gb_shared::command::{Command, JoypadButton};
+
+    const CODE: &[u8] = &[
+        0x3E, 0x20, // 0x0150: LD A,0x20
+        0xE0, 0x00, // 0x0152: LDH (0x00),A
+        0x10, 0x00, // 0x0154: STOP
+        0x3E, 0x42, // 0x0156: LD A,0x42
+        0x18, 0xFE, // 0x0158: JR -2
+    ];
+
+    #[test]
+    fn wake_up_from_stop_by_joypad() {
+        let mut gb = common::boot(CODE);
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0158));
+
+        let reason = gb.run_until_break(100_000);
+        assert_eq!(reason, BreakReason::ClocksExhausted);
+        assert!(gb.stopped());
+        assert_eq!(gb.registers().pc, 0x0156);
+
+        // Buttons are not selected.
+        gb.exec_command(Command::MutateJoypadButtons(JoypadButton::A as u8));
+        assert_eq!(gb.run_until_break(100_000), BreakReason::ClocksExhausted);
+        assert!(gb.stopped());
+
+        gb.exec_command(Command::MutateJoypadButtons(JoypadButton::Right as u8));
+        let reason = gb.run_until_break(100_000);
+        assert_eq!(reason, BreakReason::Breakpoint { addr: 0x0158, bank: 0 });
+        assert!(!gb.stopped());
+        assert_eq!(gb.registers().a, 0x42);
+    }
+}
+
+mod link_cable {
+    use super::common;
+    use gb::{LinkCable, TcpLink};
+    use std::{net::TcpListener, time::Duration};
+
+    /// Send `byte` and wait until the transfer finishes, then keep the
+    /// byte received in A.
+    fn transfer(byte: u8, control: u8) -> Vec<u8> {
+        vec![
+            0x3E, byte, // 0x0150: LD A,byte
+            0xE0, 0x01, // 0x0152: LDH (0x01),A
+            0x3E, control, // 0x0154: LD A,control
+            0xE0, 0x02, // 0x0156: LDH (0x02),A
+            0xF0, 0x02, // 0x0158: LDH A,(0x02)
+            0xCB, 0x7F, // 0x015A: BIT 7,A
+            0x20, 0xFA, // 0x015C: JR NZ,-6
+            0xF0, 0x01, // 0x015E: LDH A,(0x01)
+            0x18, 0xFE, // 0x0160: JR -2
+        ]
+    }
+
+    fn a(cable: &LinkCable, index: usize) -> u8 {
+        cable.gameboys()[index].registers().a
+    }
+
+    #[test]
+    fn exchange_bytes() {
+        let master = common::boot(&transfer(0x42, 0x81));
+        let slave = common::boot(&transfer(0x99, 0x80));
+        let mut cable = LinkCable::new(master, slave);
+
+        // A byte takes 4096 clocks at 8192Hz.
+        cable.continue_clocks(3000);
+        assert_ne!(a(&cable, 0), 0x99);
+        assert_ne!(a(&cable, 1), 0x42);
+
+        cable.continue_clocks(10_000);
+        assert_eq!(a(&cable, 0), 0x99);
+        assert_eq!(a(&cable, 1), 0x42);
+    }
+
+    #[test]
+    fn receive_0xff_after_disconnected() {
+        let master = common::boot(&transfer(0x42, 0x81));
+        let slave = common::boot(&transfer(0x99, 0x80));
+        let [mut master, mut slave] = LinkCable::new(master, slave).disconnect();
+
+        master.continue_clocks(10_000);
+        slave.continue_clocks(10_000);
+        assert_eq!(master.registers().a, 0xFF);
+        // Waiting forever, with SC read into A.
+        assert_eq!(slave.registers().a, 0xFE);
+    }
+
+    #[test]
+    fn exchange_bytes_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tolerance = Duration::from_secs(1);
+        let server = std::thread::spawn(move || TcpLink::accept(&listener, tolerance).unwrap());
+        let client = TcpLink::connect(addr, tolerance).unwrap();
+        let server = server.join().unwrap();
+
+        let mut master = common::boot(&transfer(0x42, 0x81));
+        let mut slave = common::boot(&transfer(0x99, 0x80));
+        master.replace_serial_link(Some(Box::new(client)));
+        slave.replace_serial_link(Some(Box::new(server)));
+
+        // Slave must be waiting before master's transfer finishes.
+        slave.continue_clocks(1000);
+        master.continue_clocks(10_000);
+        slave.continue_clocks(1000);
+
+        assert_eq!(master.registers().a, 0x99);
+        assert_eq!(slave.registers().a, 0x42);
+    }
+}
+
+mod serial_output {
+    use super::common;
+    use gb::{Cartridge, GameBoy, Manifest};
+    use gb_shared::CPU_FREQ;
+    use std::sync::{Arc, Mutex};
+
+    /// Send "OK" over serial.
+    const CODE: &[u8] = &[
+        0x3E, b'O', // 0x0150: LD A,'O'
+        0xCD, 0x60, 0x01, // 0x0152: CALL 0x0160
+        0x3E, b'K', // 0x0155: LD A,'K'
+        0xCD, 0x60, 0x01, // 0x0157: CALL 0x0160
+        0x18, 0xFE, // 0x015A: JR -2
+        0x00, 0x00, 0x00, 0x00, 0x00, //
+        0xE0, 0x01, // 0x0160: LDH (0x01),A
+        0x3E, 0x81, // 0x0162: LD A,0x81
+        0xE0, 0x02, // 0x0164: LDH (0x02),A
+        0xF0, 0x02, // 0x0166: LDH A,(0x02)
+        0xCB, 0x7F, // 0x0168: BIT 7,A
+        0x20, 0xFA, // 0x016A: JR NZ,-6
+        0xC9, // 0x016C: RET
+    ];
+
+    #[test]
+    fn capture_serial_output() {
+        let mut gb = common::boot(CODE);
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        gb.replace_serial_output_handle(Some(Box::new(move |byte| {
+            received_clone.lock().unwrap().push(byte);
+        })));
+        assert!(gb.take_serial_output().is_empty());
+
+        gb.capture_serial_output(true);
+        gb.continue_clocks(20_000);
+
+        assert_eq!(gb.take_serial_output(), b"OK");
+        assert!(gb.take_serial_output().is_empty());
+        assert_eq!(*received.lock().unwrap(), b"OK");
+    }
+
+    /// Run `roms/gb-test-roms/cpu_instrs/cpu_instrs.gb`, which takes a while.
+    #[test]
+    #[ignore = "requires the roms/gb-test-roms submodule, run with --ignored"]
+    fn blargg_cpu_instrs() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../roms/gb-test-roms/cpu_instrs/cpu_instrs.gb"
+        );
+        let rom = std::fs::read(path).unwrap_or_else(|err| panic!("{}: {}", path, err));
+
+        let cart = Cartridge::try_from(rom).unwrap();
+        let mut gb = GameBoy::new(Manifest::new(cart));
+        gb.capture_serial_output(true);
+
+        let mut output = vec![];
+        for _ in 0..120 {
+            gb.continue_clocks(CPU_FREQ);
+            output.extend(gb.take_serial_output());
+
+            let text = String::from_utf8_lossy(&output);
+            if text.contains("Passed") || text.contains("Failed") {
+                break;
+            }
+        }
+
+        let text = String::from_utf8_lossy(&output);
+        assert!(text.contains("Passed all tests"), "{}", text);
+    }
+}
+
+mod boot_rom {
+    use super::common;
+    use gb::{BreakReason, Breakpoint};
+    use gb_shared::CPU_FREQ;
+
+    /// LD A,(0x0000); LD B,A; LD A,(0x0200); LD C,A
+    const CODE: &[u8] = &[0xFA, 0x00, 0x00, 0x47, 0xFA, 0x00, 0x02, 0x4F];
+
+    /// Run `CODE` from 0x0000, then unmap boot ROM and run into 0x0100.
+    fn boot_rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0x00; size];
+        rom[..CODE.len()].copy_from_slice(CODE);
+        // LD A,1; LDH (0x50),A
+        rom[0xFC..0x100].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        if size > 0x200 {
+            rom[0x200] = 0x99;
+        }
+        rom
+    }
+
+    #[test]
+    fn run_dmg_boot_rom() {
+        let mut gb = common::boot_with_boot_rom(CODE, boot_rom(0x100));
+        assert_eq!(gb.registers().af(), 0x0000);
+        assert_eq!(gb.registers().sp, 0x0000);
+
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0100));
+        assert_eq!(gb.run_until_break(CPU_FREQ), BreakReason::Breakpoint { addr: 0x0100, bank: 0 });
+        // Read from boot ROM, while 0x0200 is mapped to cartridge.
+        assert_eq!(gb.registers().bc(), 0xFA00);
+
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0158));
+        assert_eq!(gb.run_until_break(CPU_FREQ), BreakReason::Breakpoint { addr: 0x0158, bank: 0 });
+        // Read from cartridge after unmapped.
+        assert_eq!(gb.registers().bc(), 0x0000);
+    }
+
+    #[test]
+    fn run_cgb_boot_rom() {
+        let mut gb = common::boot_with_boot_rom(CODE, boot_rom(0x900));
+
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0100));
+        assert_eq!(gb.run_until_break(CPU_FREQ), BreakReason::Breakpoint { addr: 0x0100, bank: 0 });
+        // 0x0200 is mapped to boot ROM too.
+        assert_eq!(gb.registers().bc(), 0xFA99);
+    }
+}
+
+mod hardware_model {
+    use super::common;
+    use gb::{Cartridge, GameBoy, HardwareModel, Manifest};
+
+    /// LDH A,(0x04); JR -2
+    const CODE: &[u8] = &[0xF0, 0x04, 0x18, 0xFE];
+
+    #[test]
+    fn model_follows_cartridge() {
+        assert_eq!(common::boot(CODE).model(), HardwareModel::DMG);
+
+        let rom = common::build_rom_with(CODE, |rom| rom[0x0143] = 0xC0);
+        let gb = GameBoy::new(Manifest::new(Cartridge::try_from(rom).unwrap()));
+        assert_eq!(gb.model(), HardwareModel::CGBE);
+    }
+
+    #[test]
+    fn post_boot_registers_by_model() {
+        let rom = common::build_rom_with(CODE, |rom| rom[0x0143] = 0x80);
+        for (model, af, de) in
+            [(HardwareModel::DMG, 0x01B0, 0x00D8), (HardwareModel::CGBE, 0x1180, 0xFF56)]
+        {
+            let cart = Cartridge::try_from(rom.clone()).unwrap();
+            let gb = GameBoy::new(Manifest::new(cart).with_model(model));
+            assert_eq!(gb.model(), model);
+            assert_eq!([gb.registers().af(), gb.registers().de()], [af, de], "{:?}", model);
+        }
+    }
+}
+
+mod compatibility_mode {
+    use super::common;
+    use gb::{BreakReason, Breakpoint, Cartridge, GameBoy, HardwareModel, Manifest};
+
+    const CODE: &[u8] = &[
+        0x3E, 0x02, 0xE0, 0x70, // LD A,0x02; LDH (0x70),A
+        0x3E, 0x12, 0xE0, 0x72, // LD A,0x12; LDH (0x72),A
+        0x3E, 0x34, 0xE0, 0x74, // LD A,0x34; LDH (0x74),A
+        0xF0, 0x70, 0x47, // LDH A,(0x70); LD B,A
+        0xF0, 0x72, 0x4F, // LDH A,(0x72); LD C,A
+        0xF0, 0x74, 0x57, // LDH A,(0x74); LD D,A
+        0xF0, 0x6C, 0x5F, // LDH A,(0x6C); LD E,A
+        0xF0, 0x4C, 0x67, // LDH A,(0x4C); LD H,A
+        0xF0, 0x4F, 0x6F, // LDH A,(0x4F); LD L,A
+        0x18, 0xFE, // JR -2
+    ];
+
+    /// Returns A after boot, and SVBK, FF72, FF74, OPRI, KEY0 and VBK.
+    fn run(cgb_flag: u8, model: HardwareModel) -> (u8, [u8; 6]) {
+        let rom = common::build_rom_with(CODE, |rom| rom[0x0143] = cgb_flag);
+        let cart = Cartridge::try_from(rom).unwrap();
+        let mut gb = GameBoy::new(Manifest::new(cart).with_model(model));
+        let a = gb.registers().a;
+
+        let end = 0x0150 + CODE.len() as u16 - 2;
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(end));
+        assert_eq!(gb.run_until_break(10_000), BreakReason::Breakpoint { addr: end, bank: 0 });
+        let regs = gb.registers();
+
+        (a, [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l])
+    }
+
+    #[test]
+    fn dmg_cartridge_on_cgb() {
+        assert_eq!(run(0x00, HardwareModel::CGBE), (0x11, [0xFF, 0x12, 0xFF, 0xFF, 0xFF, 0xFF]));
+    }
+
+    #[test]
+    fn cgb_cartridge_on_cgb() {
+        assert_eq!(run(0x80, HardwareModel::CGBE), (0x11, [0xFA, 0x12, 0x34, 0xFE, 0xFF, 0xFE]));
+        assert_eq!(run(0xC0, HardwareModel::AGB), (0x11, [0xFA, 0x12, 0x34, 0xFE, 0xFF, 0xFE]));
+    }
+
+    #[test]
+    fn cgb_cartridge_on_dmg() {
+        assert_eq!(run(0x80, HardwareModel::DMG), (0x01, [0xFF; 6]));
+        // It's up to the cartridge to tell it's not running on CGB.
+        assert_eq!(run(0xC0, HardwareModel::MGB), (0xFF, [0xFF; 6]));
+    }
+
+    #[test]
+    fn enter_compatibility_mode_by_boot_rom() {
+        let mut boot_rom = vec![0x00; 0x900];
+        boot_rom[..12].copy_from_slice(&[
+            0x3E, 0x04, 0xE0, 0x4C, // LD A,0x04; LDH (0x4C),A
+            0x3E, 0x01, 0xE0, 0x6C, // LD A,0x01; LDH (0x6C),A
+            0xF0, 0x4C, 0x47, // LDH A,(0x4C); LD B,A
+            0x00,
+        ]);
+        // LD A,1; LDH (0x50),A
+        boot_rom[0xFC..0x100].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+
+        let mut gb = common::boot_with_boot_rom(CODE, boot_rom);
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(0x0100));
+        assert_eq!(gb.run_until_break(10_000), BreakReason::Breakpoint { addr: 0x0100, bank: 0 });
+        assert_eq!(gb.registers().bc() >> 8, 0x04);
+
+        let end = 0x0150 + CODE.len() as u16 - 2;
+        gb.debugger_mut().add_breakpoint(Breakpoint::new(end));
+        assert_eq!(gb.run_until_break(10_000), BreakReason::Breakpoint { addr: end, bank: 0 });
+        let regs = gb.registers();
+        assert_eq!([regs.bc(), regs.de(), regs.hl()], [0xFF12, 0xFFFF, 0xFFFF]);
+    }
+}
+
+mod threads {
+    use super::common;
+    use std::sync::{Arc, Mutex};
+
+    /// Count down B in a loop.
+    const CODE: &[u8] = &[
+        0x05, // 0x0150: DEC B
+        0x18, 0xFD, // 0x0151: JR -3
+    ];
+
+    #[test]
+    fn run_on_another_thread() {
+        let mut local = common::boot(CODE);
+        let mut remote = common::boot(CODE);
+        let frames = Arc::new(Mutex::new(0));
+        let frames_clone = frames.clone();
+        remote.replace_frame_handle(Some(Box::new(move |_| {
+            *frames_clone.lock().unwrap() += 1;
+        })));
+
+        let remote = std::thread::spawn(move || {
+            remote.continue_clocks(common::CLOCKS_PER_FRAME * 3);
+            remote
+        });
+        local.continue_clocks(common::CLOCKS_PER_FRAME * 3);
+        let remote = remote.join().unwrap();
+
+        assert!(*frames.lock().unwrap() >= 2);
+        assert_eq!(remote.registers().pc, local.registers().pc);
+        assert_eq!(remote.registers().bc(), local.registers().bc());
+    }
+}
+
+mod fork {
+    use super::common;
+    use gb::GameBoy;
+    use gb_shared::command::{Command, JoypadButton};
+    use gb_shared::Snapshot;
+
+    /// Keep reading buttons into B.
+    const CODE: &[u8] = &[
+        0x3E, 0x10, // 0x0150: LD A,0x10
+        0xE0, 0x00, // 0x0152: LDH (0x00),A
+        0xF0, 0x00, // 0x0154: LDH A,(0x00)
+        0x47, // 0x0156: LD B,A
+        0x18, 0xFB, // 0x0157: JR -5
+    ];
+
+    fn state(gb: &GameBoy) -> Vec<u8> {
+        Vec::try_from(&gb.take_snapshot()).unwrap()
+    }
+
+    #[test]
+    fn run_the_same_as_origin() {
+        let mut gb = common::boot(CODE);
+        gb.continue_clocks(common::CLOCKS_PER_FRAME / 3);
+
+        let mut fork = gb.fork();
+        assert_eq!(state(&fork), state(&gb));
+
+        gb.continue_clocks(common::CLOCKS_PER_FRAME * 2);
+        fork.continue_clocks(common::CLOCKS_PER_FRAME * 2);
+        assert_eq!(state(&fork), state(&gb));
+    }
+
+    #[test]
+    fn run_independently() {
+        let mut gb = common::boot(CODE);
+        gb.continue_clocks(common::CLOCKS_PER_FRAME);
+
+        let mut fork = gb.fork();
+        fork.exec_command(Command::MutateJoypadButtons(JoypadButton::A as u8));
+        gb.continue_clocks(common::CLOCKS_PER_FRAME);
+        fork.continue_clocks(common::CLOCKS_PER_FRAME);
+
+        assert_eq!(gb.registers().bc() >> 8, 0xDF);
+        assert_eq!(fork.registers().bc() >> 8, 0xDE);
+    }
+}
+
+mod shared_rom {
+    use super::common;
+    use gb::{Cartridge, GameBoy, Manifest};
+    use std::sync::Arc;
+
+    /// LD A,0x42; JR -2
+    const CODE: &[u8] = &[0x3E, 0x42, 0x18, 0xFE];
+
+    #[test]
+    fn share_rom_across_instances() {
+        let rom: Arc<[u8]> = common::build_rom(CODE).into();
+        let mut gameboys =
+            [0, 1].map(|_| GameBoy::new(Manifest::new(Cartridge::try_from(rom.clone()).unwrap())));
+        assert_eq!(Arc::strong_count(&rom), 3);
+
+        for gb in gameboys.iter_mut() {
+            gb.continue_clocks(10_000);
+            assert_eq!(gb.registers().a, 0x42);
+        }
+
+        let fork = gameboys[0].fork();
+        assert_eq!(Arc::strong_count(&rom), 4);
+        drop(fork);
+        drop(gameboys);
+        assert_eq!(Arc::strong_count(&rom), 1);
+    }
+
+    #[test]
+    fn borrow_static_rom() {
+        let rom: &'static [u8] = common::build_rom(CODE).leak();
+        let cart = Cartridge::try_from(rom).unwrap();
+        let mut gb = GameBoy::new(Manifest::new(cart));
+
+        gb.continue_clocks(10_000);
+        assert_eq!(gb.registers().a, 0x42);
+    }
+}
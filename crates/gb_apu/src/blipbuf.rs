@@ -4,6 +4,8 @@ pub(crate) struct BlipBuf {
     buf: Blip,
     volume: i32,
     clock_time: u32,
+    frequency: u32,
+    sample_rate: u32,
 }
 
 impl BlipBuf {
@@ -11,7 +13,7 @@ impl BlipBuf {
         let mut blipbuf = Blip::new(sample_rate);
         blipbuf.set_rates(f64::from(frequency), f64::from(sample_rate));
 
-        Self { buf: blipbuf, volume: init_volume, clock_time: 0 }
+        Self { buf: blipbuf, volume: init_volume, clock_time: 0, frequency, sample_rate }
     }
 
     pub(crate) fn add_delta(&mut self, duration: u32, volume: i32) {
@@ -34,3 +36,14 @@ impl BlipBuf {
         self.buf.clear();
     }
 }
+
+/// [`Blip`] can't be cloned, so samples not read yet are dropped, like
+/// restoring a snapshot.
+impl Clone for BlipBuf {
+    fn clone(&self) -> Self {
+        Self {
+            clock_time: self.clock_time,
+            ..Self::new(self.frequency, self.sample_rate, self.volume)
+        }
+    }
+}
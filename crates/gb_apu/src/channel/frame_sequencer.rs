@@ -1,22 +1,30 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{clock::Clock, utils::freq_to_period};
+use gb_shared::{Snapshot, CPU_FREQ};
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Clocked by a falling edge of DIV bit 4, or bit 5 in double speed.
+/// @see https://gbdev.io/pandocs/Audio_details.html#div-apu
+#[derive(Clone)]
 pub(crate) struct FrameSequencer {
-    clock: Clock,
     frame: Frame,
+    /// Whether it's clocked since last step.
+    clocked: bool,
 }
 
 impl FrameSequencer {
-    const FRAME_SEQUENCY_PERIOD: u32 = freq_to_period(512);
+    /// Period of the clock it used to run off, see [`FrameSequencerSnapshot`].
+    const PERIOD: u32 = CPU_FREQ / 512;
 
     pub(crate) fn new() -> Self {
-        Self { clock: Clock::new(Self::FRAME_SEQUENCY_PERIOD), frame: Default::default() }
+        Self { frame: Default::default(), clocked: false }
+    }
+
+    pub(crate) fn clock(&mut self) {
+        self.clocked = true;
     }
 
     pub(crate) fn step(&mut self) -> Option<Frame> {
-        if self.clock.step() {
+        if std::mem::take(&mut self.clocked) {
             self.frame.0 = (self.frame.0 + 1) & 0x7;
             Some(self.frame)
         } else {
@@ -26,6 +34,32 @@ impl FrameSequencer {
 
     pub(crate) fn power_off(&mut self) {
         self.frame = Default::default();
+        self.clocked = false;
+    }
+}
+
+/// Same layout as the frame sequencer had when it ran off its own clock,
+/// i.e. `(period, clocks)`, so that older snapshots can be restored.
+/// That clock never reached its period, which marks being clocked since
+/// last step now.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct FrameSequencerSnapshot {
+    clock: (u32, u32),
+    frame: Frame,
+}
+
+impl Snapshot for FrameSequencer {
+    type Snapshot = FrameSequencerSnapshot;
+
+    fn take_snapshot(&self) -> Self::Snapshot {
+        let clocks = if self.clocked { Self::PERIOD } else { 0 };
+        FrameSequencerSnapshot { clock: (Self::PERIOD, clocks), frame: self.frame }
+    }
+
+    fn restore_snapshot(&mut self, snapshot: Self::Snapshot) {
+        let (period, clocks) = snapshot.clock;
+        self.frame = snapshot.frame;
+        self.clocked = period != 0 && clocks == period;
     }
 }
 
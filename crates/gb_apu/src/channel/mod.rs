@@ -7,7 +7,7 @@ mod sweep;
 mod wave_channel;
 
 use envelope::Envelope;
-pub(crate) use frame_sequencer::{Frame, FrameSequencer};
+pub(crate) use frame_sequencer::{Frame, FrameSequencer, FrameSequencerSnapshot};
 use length_counter::{
     NoiseChannelLengthCounter, PulseChannelLengthCounter, WaveChannelLengthCounter,
 };
@@ -64,6 +64,7 @@ impl Lfsr {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct NoiseChannel {
     /// Length timer.
     nrx1: u8,
@@ -126,7 +127,8 @@ impl NoiseChannel {
     }
 
     #[inline]
-    pub(crate) fn step(&mut self, frame: Option<Frame>) {
+    /// Shift the LFSR, which is only audible.
+    pub(crate) fn step_clock(&mut self) {
         if let Some(use_volume) = self.lfsr.step(self.nrx3) {
             let volume =
                 if use_volume && (self.active()) { self.envelope.volume() as i32 } else { 0 };
@@ -134,7 +136,10 @@ impl NoiseChannel {
                 blipbuf.add_delta(self.lfsr.clock.div(), volume);
             }
         }
+    }
 
+    /// Step the envelope and length counter on a frame of the frame sequencer.
+    pub(crate) fn step_frame(&mut self, frame: Option<Frame>) {
         if let Some(frame) = frame {
             if self.active {
                 self.envelope.step(frame);
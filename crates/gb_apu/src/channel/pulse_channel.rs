@@ -5,6 +5,7 @@ use crate::{blipbuf, clock::Clock};
 
 use super::{Envelope, Frame, PulseChannelLengthCounter as LengthCounter, Sweep};
 
+#[derive(Clone)]
 struct PulseChannelClock(Clock);
 
 impl PulseChannelClock {
@@ -65,6 +66,7 @@ impl DutyCycle {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct PulseChannel<SWEEP>
 where
     SWEEP: Sweep,
@@ -153,7 +155,8 @@ where
         self.active
     }
 
-    pub(crate) fn step(&mut self, frame: Option<Frame>) {
+    /// Advance the duty cycle, which is only audible.
+    pub(crate) fn step_clock(&mut self) {
         if self.channel_clock.step() {
             let volume = if self.active() {
                 let is_high_signal = self.duty_cycle.step(self.nrx1);
@@ -170,7 +173,10 @@ where
                 blipbuf.add_delta(self.channel_clock.div(), volume);
             }
         }
+    }
 
+    /// Step the sweep, envelope and length counter on a frame of the frame sequencer.
+    pub(crate) fn step_frame(&mut self, frame: Option<Frame>) {
         if let Some(frame) = frame {
             if self.sweep.step(frame) {
                 self.channel_clock.reload(self.sweep.period_value());
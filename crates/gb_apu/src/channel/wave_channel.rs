@@ -12,6 +12,7 @@ enum OutputLevel {
     Quarter,
 }
 
+#[derive(Clone)]
 pub(crate) struct WaveRam {
     ram: [u8; 16],
     index: u8,
@@ -63,6 +64,7 @@ impl Memory for WaveRam {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct WaveChannel {
     /// DAC enable.
     /// Bit7, 1: On, 0: Off
@@ -160,7 +162,8 @@ impl WaveChannel {
         }
     }
 
-    pub(crate) fn step(&mut self, frame: Option<Frame>) {
+    /// Advance the position in wave RAM, which is only audible.
+    pub(crate) fn step_clock(&mut self) {
         if self.channel_clock.step() {
             let volume = if self.active() {
                 let volume = self.wave_ram.next_position();
@@ -179,7 +182,10 @@ impl WaveChannel {
                 blipbuf.add_delta(self.channel_clock.div(), volume);
             }
         }
+    }
 
+    /// Step the length counter on a frame of the frame sequencer.
+    pub(crate) fn step_frame(&mut self, frame: Option<Frame>) {
         if let Some(frame) = frame {
             self.length_counter.step(frame);
         }
@@ -13,6 +13,11 @@ impl Clock {
         self.div
     }
 
+    /// Clocks until next emitting.
+    pub(crate) fn remaining(&self) -> u32 {
+        self.div - self.clocks
+    }
+
     pub(crate) fn step(&mut self) -> bool {
         if self.div == 0 {
             return false;
@@ -1,15 +1,17 @@
 mod blipbuf;
 mod channel;
 mod clock;
-mod utils;
 
 use channel::{
     Channel1, Channel1Snapshot, Channel2, Channel2Snapshot, Channel3, Channel3Snapshot, Channel4,
-    Channel4Snapshot, FrameSequencer,
+    Channel4Snapshot, FrameSequencer, FrameSequencerSnapshot,
 };
 use clock::Clock;
-use gb_shared::{is_bit_set, Memory, Snapshot, CPU_FREQ};
+use gb_shared::{is_bit_set, MachineModel, Memory, Snapshot, CPU_FREQ};
 
+#[cfg(not(target_family = "wasm"))]
+pub type AudioHandle = dyn FnMut(&[(f32, f32)]) + Send;
+#[cfg(target_family = "wasm")]
 pub type AudioHandle = dyn FnMut(&[(f32, f32)]);
 
 pub struct Apu {
@@ -46,6 +48,9 @@ pub struct Apu {
     samples_buffer: Vec<i16>,
     mixed_samples_buffer: Vec<(f32, f32)>,
     fs: FrameSequencer,
+    machine_model: MachineModel,
+    /// Clocks passed but not run yet, see [`Apu::sync`].
+    pending: u32,
 }
 
 const MIXER_FREQ: u32 = 64;
@@ -54,12 +59,34 @@ pub fn buffer_size_from_sample_rate(sample_rate: u32) -> u32 {
     sample_rate.div_ceil(MIXER_FREQ)
 }
 
+/// The audio handle is not cloned.
+impl Clone for Apu {
+    fn clone(&self) -> Self {
+        Self {
+            ch1: self.ch1.clone(),
+            ch2: self.ch2.clone(),
+            ch3: self.ch3.clone(),
+            ch4: self.ch4.clone(),
+            mixer_clock: self.mixer_clock.clone(),
+            nr50: self.nr50,
+            nr51: self.nr51,
+            nr52: self.nr52,
+            audio_handle: None,
+            samples_buffer: self.samples_buffer.clone(),
+            mixed_samples_buffer: self.mixed_samples_buffer.clone(),
+            fs: self.fs.clone(),
+            machine_model: self.machine_model,
+            pending: self.pending,
+        }
+    }
+}
+
 impl Apu {
     fn new_mixer_clock() -> Clock {
         Clock::new(gb_shared::CPU_FREQ / MIXER_FREQ)
     }
 
-    pub fn new(sample_rate: Option<u32>) -> Self {
+    pub fn new(machine_model: MachineModel, sample_rate: Option<u32>) -> Self {
         let frequency = CPU_FREQ;
         let buffer_size = sample_rate.map_or(0, buffer_size_from_sample_rate) as usize;
         let fs = FrameSequencer::new();
@@ -76,6 +103,8 @@ impl Apu {
             samples_buffer: vec![0; buffer_size],
             mixed_samples_buffer: vec![(0.0, 0.0); buffer_size],
             fs,
+            machine_model,
+            pending: 0,
         };
 
         log::trace!("APU is created: {:?}", instance);
@@ -89,6 +118,13 @@ impl Apu {
         self.ch3.power_off();
         self.ch4.power_off();
         self.fs.power_off();
+        if self.machine_model == MachineModel::CGB {
+            // On CGB, length counters are reset.
+            self.ch1.set_length_counter(0);
+            self.ch2.set_length_counter(0);
+            self.ch3.set_length_counter(0);
+            self.ch4.set_length_counter(0);
+        }
         self.mixer_clock = Self::new_mixer_clock();
         self.nr50 = 0;
         self.nr51 = 0;
@@ -111,18 +147,70 @@ impl Apu {
         self.nr50 & 0b111
     }
 
-    pub fn step(&mut self) {
-        if !self.audio_on() {
+    /// Whether samples are consumed, otherwise only the state visible to
+    /// registers is kept, e.g. NR52 and length counters.
+    #[inline]
+    fn output_enabled(&self) -> bool {
+        !self.samples_buffer.is_empty() && self.audio_handle.is_some()
+    }
+
+    /// Called on a falling edge of DIV bit 4, or bit 5 in double speed.
+    /// @see https://gbdev.io/pandocs/Audio_details.html#div-apu
+    pub fn clock_frame_sequencer(&mut self) {
+        self.sync();
+        if self.audio_on() {
+            self.fs.clock();
+        }
+    }
+
+    /// Pass `clocks`, which are run lazily by [`Apu::sync`] when the mixer
+    /// needs samples, or something else does.
+    #[inline]
+    pub fn step(&mut self, clocks: u32) {
+        self.pending = self.pending.saturating_add(clocks);
+        if self.output_enabled() && self.pending >= self.mixer_clock.remaining() {
+            self.sync();
+        }
+    }
+
+    /// Run clocks passed, before accessing registers, clocking the frame
+    /// sequencer, etc.
+    ///
+    /// Without output, channel timers and the mixer are not run, as
+    /// nothing but the frame sequencer changes the state visible to
+    /// registers, and it's clocked once at most since last sync.
+    pub fn sync(&mut self) {
+        let clocks = std::mem::take(&mut self.pending);
+        if clocks == 0 || !self.audio_on() {
             return;
         }
 
-        let frame = self.fs.step();
-        self.ch1.step(frame);
-        self.ch2.step(frame);
-        self.ch3.step(frame);
-        self.ch4.step(frame);
+        if !self.output_enabled() {
+            let frame = self.fs.step();
+            self.ch1.step_frame(frame);
+            self.ch2.step_frame(frame);
+            self.ch3.step_frame(frame);
+            self.ch4.step_frame(frame);
+            return;
+        }
 
-        if self.mixer_clock.step() && !self.samples_buffer.is_empty() {
+        for _ in 0..clocks {
+            self.step_clock();
+        }
+    }
+
+    fn step_clock(&mut self) {
+        let frame = self.fs.step();
+        self.ch1.step_clock();
+        self.ch1.step_frame(frame);
+        self.ch2.step_clock();
+        self.ch2.step_frame(frame);
+        self.ch3.step_clock();
+        self.ch3.step_frame(frame);
+        self.ch4.step_clock();
+        self.ch4.step_frame(frame);
+
+        if self.mixer_clock.step() {
             let left_volume_coefficient =
                 ((self.master_left_volume() + 1) as f32 / 8.0) * (1.0 / 15.0) * 0.25;
             let right_volume_coefficient =
@@ -174,31 +262,20 @@ impl Apu {
 
 impl Memory for Apu {
     fn write(&mut self, addr: u16, value: u8) {
+        self.sync();
+
         // All registers except NR52 are read-only when APU is disabled.
         // @see https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-4--noise:~:text=makes%20them%20read-only%20until%20turned%20back%20on
         if !self.audio_on() {
-            if addr == 0xFF11 {
-                // On DMG, length counter are unaffected by power and can still be written while off.
-                self.ch1.set_length_counter(value & 0x3F);
-                return;
-            }
-
-            if addr == 0xFF16 {
-                // On DMG, length counter are unaffected by power and can still be written while off.
-                self.ch2.set_length_counter(value & 0x3F);
-                return;
-            }
-
-            if addr == 0xFF1B {
-                self.ch3.set_length_counter(value);
-                // On DMG, length counter are unaffected by power and can still be written while off.
-                return;
-            }
-
-            if addr == 0xFF20 {
-                // On DMG, length counter are unaffected by power and can still be written while off.
-                self.ch4.set_length_counter(value & 0x3F);
-                return;
+            // On DMG, length counter are unaffected by power and can still be written while off.
+            if self.machine_model == MachineModel::DMG {
+                match addr {
+                    0xFF11 => self.ch1.set_length_counter(value & 0x3F),
+                    0xFF16 => self.ch2.set_length_counter(value & 0x3F),
+                    0xFF1B => self.ch3.set_length_counter(value),
+                    0xFF20 => self.ch4.set_length_counter(value & 0x3F),
+                    _ => {}
+                }
             }
 
             if addr != 0xFF26 // NR52
@@ -324,13 +401,15 @@ pub struct ApuSnapshot {
     nr50: u8,
     nr51: u8,
     nr52: u8,
-    fs: FrameSequencer,
+    fs: FrameSequencerSnapshot,
 }
 
 impl Snapshot for Apu {
     type Snapshot = ApuSnapshot;
 
+    /// Clocks passed are not kept, so it must be synced, see [`Apu::sync`].
     fn take_snapshot(&self) -> Self::Snapshot {
+        debug_assert_eq!(self.pending, 0, "APU is not synced");
         ApuSnapshot {
             ch1: self.ch1.take_snapshot(),
             ch2: self.ch2.take_snapshot(),
@@ -340,7 +419,7 @@ impl Snapshot for Apu {
             nr50: self.nr50,
             nr51: self.nr51,
             nr52: self.nr52,
-            fs: self.fs.clone(),
+            fs: self.fs.take_snapshot(),
         }
     }
 
@@ -353,6 +432,109 @@ impl Snapshot for Apu {
         self.nr50 = snapshot.nr50;
         self.nr51 = snapshot.nr51;
         self.nr52 = snapshot.nr52;
-        self.fs = snapshot.fs;
+        self.fs.restore_snapshot(snapshot.fs);
+        self.pending = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Trigger CH1 and CH2 with length 16 and 32, respectively, and run
+    /// until both are off. Returns frame sequencer clocks by then.
+    fn expire_length_counters(apu: &mut Apu) -> [usize; 2] {
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF11, 0x30);
+        apu.write(0xFF16, 0x20);
+        apu.write(0xFF14, 0xC0);
+        apu.write(0xFF19, 0xC0);
+
+        let mut expired = [0; 2];
+        for frame in 1..=128 {
+            // The frame sequencer is clocked at 512Hz.
+            apu.step(CPU_FREQ / 512);
+            apu.clock_frame_sequencer();
+            apu.sync();
+            let nr52 = apu.read(0xFF26);
+            for (ch, expired) in expired.iter_mut().enumerate() {
+                if *expired == 0 && !is_bit_set!(nr52, ch) {
+                    *expired = frame;
+                }
+            }
+        }
+
+        expired
+    }
+
+    #[test]
+    fn expire_length_counters_without_output() {
+        let samples = Arc::new(AtomicUsize::new(0));
+        let mut with_output = Apu::new(MachineModel::DMG, Some(48_000));
+        with_output.audio_handle = Some(Box::new({
+            let samples = samples.clone();
+            move |buffer: &[(f32, f32)]| {
+                samples.fetch_add(buffer.len(), Ordering::Relaxed);
+            }
+        }));
+        // Length counters are clocked at 256Hz.
+        assert_eq!(expire_length_counters(&mut with_output), [32, 64]);
+        assert!(samples.load(Ordering::Relaxed) > 0);
+
+        // Samples are not consumed without either sample rate or handle.
+        let mut without_handle = Apu::new(MachineModel::DMG, Some(48_000));
+        assert_eq!(expire_length_counters(&mut without_handle), [32, 64]);
+        let mut without_sample_rate = Apu::new(MachineModel::DMG, None);
+        assert_eq!(expire_length_counters(&mut without_sample_rate), [32, 64]);
+    }
+
+    #[test]
+    fn restore_synced_snapshot() {
+        let mut apu = Apu::new(MachineModel::DMG, None);
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 0x3F);
+        apu.write(0xFF14, 0xC0);
+        apu.step(CPU_FREQ / 512);
+        apu.clock_frame_sequencer();
+        apu.sync();
+
+        let mut restored = Apu::new(MachineModel::DMG, None);
+        restored.restore_snapshot(apu.take_snapshot());
+        assert_eq!(restored.read(0xFF26), 0xF1);
+
+        // The frame sequencer clocked before taking the snapshot expires
+        // the length counter of CH1.
+        restored.step(4);
+        restored.sync();
+        assert_eq!(restored.read(0xFF26), 0xF0);
+    }
+
+    /// Serialized by the APU whose frame sequencer ran off its own clock,
+    /// with CH1 playing, 5 frames and 100 clocks after turned on.
+    const BASELINE_SNAPSHOT: &[u8] = &[
+        0, 48, 240, 0, 32, 0, 0, 100, 0, 0, 0, 4, 51, 0, 1, 5, 4, 8, 8, 0, 15, 15, 8, 8, 0, 0, 0,
+        0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 32, 0, 0, 100, 0, 0, 0, 4, 64, 0, 0, 0, 7, 8, 8, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 4, 0, 1, 0, 0,
+        16, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 0, 4, 64, 0, 0, 7, 8, 8, 0, 0, 0, 94, 92, 8, 0, 0, 0,
+        4, 0, 0, 0, 0, 0, 0, 1, 0, 100, 160, 0, 0, 119, 243, 128, 0, 32, 0, 0, 100, 0, 0, 0, 4,
+    ];
+
+    #[test]
+    fn restore_baseline_snapshot() {
+        let snapshot: ApuSnapshot = bincode::deserialize(BASELINE_SNAPSHOT).unwrap();
+        let mut apu = Apu::new(MachineModel::DMG, None);
+        apu.restore_snapshot(snapshot);
+        assert_eq!(apu.read(0xFF24), 0x77);
+        assert_eq!(apu.read(0xFF25), 0xF3);
+        assert_eq!(apu.read(0xFF26), 0xF1);
+
+        // Clocks of the frame sequencer's own clock are not kept.
+        let snapshot = bincode::serialize(&apu.take_snapshot()).unwrap();
+        assert_eq!(snapshot.len(), BASELINE_SNAPSHOT.len());
     }
 }
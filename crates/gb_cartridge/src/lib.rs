@@ -1,7 +1,7 @@
 mod mbc;
 
 use gb_shared::{MachineModel, Snapshot};
-use std::{borrow::Cow, fmt::Display};
+use std::{borrow::Cow, fmt::Display, sync::Arc};
 
 const CARRIAGE_TYPE: [(u8, &str); 28] = [
     (0x00, "ROM ONLY"),
@@ -368,16 +368,76 @@ impl Display for CartridgeHeader {
     }
 }
 
+/// Anything holding ROM bytes, e.g. a memory-mapped file.
+pub type RomOwner = dyn AsRef<[u8]> + Send + Sync;
+
+/// ROM is never written, so it's shared instead of being copied, and
+/// it's not carried by snapshots.
+#[derive(Clone)]
+enum Rom {
+    Shared(Arc<[u8]>),
+    Static(&'static [u8]),
+    Owned(Arc<RomOwner>),
+}
+
+impl std::ops::Deref for Rom {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Rom::Shared(rom) => rom,
+            Rom::Static(rom) => rom,
+            Rom::Owned(owner) => (**owner).as_ref(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Cartridge {
     pub header: CartridgeHeader,
-    pub rom: Vec<u8>,
-    mbc: Box<dyn mbc::Mbc<Snapshot = Vec<u8>>>,
+    rom: Rom,
+    mbc: mbc::BoxedMbc,
 }
 
 impl TryFrom<Vec<u8>> for Cartridge {
     type Error = anyhow::Error;
 
     fn try_from(rom: Vec<u8>) -> std::result::Result<Self, Self::Error> {
+        Self::try_from(Arc::<[u8]>::from(rom))
+    }
+}
+
+/// Share the ROM with other instances, e.g. running the same game.
+impl TryFrom<Arc<[u8]>> for Cartridge {
+    type Error = anyhow::Error;
+
+    fn try_from(rom: Arc<[u8]>) -> std::result::Result<Self, Self::Error> {
+        Self::new(Rom::Shared(rom))
+    }
+}
+
+/// Borrow the ROM, e.g. embedded by `include_bytes!`.
+impl TryFrom<&'static [u8]> for Cartridge {
+    type Error = anyhow::Error;
+
+    fn try_from(rom: &'static [u8]) -> std::result::Result<Self, Self::Error> {
+        Self::new(Rom::Static(rom))
+    }
+}
+
+/// Share the ROM held by `owner` without copying it, e.g. a memory-mapped
+/// file, which is released with the last instance.
+impl TryFrom<Arc<RomOwner>> for Cartridge {
+    type Error = anyhow::Error;
+
+    fn try_from(owner: Arc<RomOwner>) -> std::result::Result<Self, Self::Error> {
+        Self::new(Rom::Owned(owner))
+    }
+}
+
+impl Cartridge {
+    fn new(rom: Rom) -> anyhow::Result<Self> {
         let header = unsafe {
             std::mem::transmute_copy::<[u8; 0x50], CartridgeHeader>(
                 &rom[0x0100..0x0150].try_into().unwrap(),
@@ -392,7 +452,7 @@ impl TryFrom<Vec<u8>> for Cartridge {
 
         log::trace!("{}", &header);
 
-        let mbc: Box<dyn mbc::Mbc<Snapshot = Vec<u8>>> = match &header.cart_type {
+        let mbc: mbc::BoxedMbc = match &header.cart_type {
             0x00 => Box::new(mbc::mbc_none::MbcNone::new()),
             0x01..=0x03 => Box::new(mbc::mbc1::Mbc1::new(&header)),
             0x05..=0x06 => Box::new(mbc::mbc2::Mbc2::new(&header)),
@@ -410,6 +470,11 @@ impl TryFrom<Vec<u8>> for Cartridge {
 
         Ok(Cartridge { header, rom, mbc })
     }
+
+    #[inline]
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
 }
 
 impl gb_shared::Memory for Cartridge {
@@ -443,6 +508,30 @@ impl Cartridge {
         self.mbc.resume(data)
     }
 
+    /// ROM bank mapped at [0x4000, 0x7FFF].
+    #[inline]
+    pub fn rom_bank(&self) -> usize {
+        self.mbc.rom_bank()
+    }
+
+    /// RAM bank mapped at [0xA000, 0xBFFF], if it's plain RAM, which can
+    /// be accessed by [`Cartridge::ram_bank_data`] directly. It's `None`
+    /// if RAM is disabled, or RTC registers are mapped instead, etc.
+    #[inline]
+    pub fn ram_bank(&self) -> Option<usize> {
+        self.mbc.ram_bank()
+    }
+
+    #[inline]
+    pub fn ram_bank_data(&self, bank: usize) -> &[u8] {
+        self.mbc.ram_banks()[bank].as_ref()
+    }
+
+    #[inline]
+    pub fn ram_bank_data_mut(&mut self, bank: usize) -> &mut [u8] {
+        self.mbc.ram_banks_mut()[bank].as_mut()
+    }
+
     pub fn machine_model(&self) -> MachineModel {
         let cgb_flag = self.header.title[15];
         match cgb_flag {
@@ -501,3 +590,40 @@ impl Cartridge {
         Some(0x0000)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom[0x014D] = rom[0x0134..0x014D]
+            .iter()
+            .fold(0u8, |checksum, v| checksum.wrapping_sub(v.wrapping_add(1)));
+        rom
+    }
+
+    struct Owner(Vec<u8>);
+
+    impl AsRef<[u8]> for Owner {
+        fn as_ref(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn share_rom_of_owner() {
+        let owner: Arc<RomOwner> = Arc::new(Owner(build_rom()));
+        let cart = Cartridge::try_from(owner.clone()).unwrap();
+        let clone = cart.clone();
+
+        assert_eq!(Arc::strong_count(&owner), 3);
+        assert_eq!(cart.rom().as_ptr(), (*owner).as_ref().as_ptr());
+        assert_eq!(clone.rom().as_ptr(), (*owner).as_ref().as_ptr());
+        assert_eq!(&cart.rom()[0x0134..0x0138], b"TEST");
+
+        drop([cart, clone]);
+        assert_eq!(Arc::strong_count(&owner), 1);
+    }
+}
@@ -4,6 +4,7 @@ use gb_shared::{box_array, kib, Snapshot};
 use serde::{Deserialize, Serialize};
 
 /// https://gbdev.io/pandocs/MBC1.html
+#[derive(Clone)]
 pub(crate) struct Mbc1 {
     /// 00h = ROM Banking Mode (up to 8KiB banked RAM, 2MiB ROM) (default)
     /// 01h = RAM Banking Mode (up to 32KiB banked RAM, 512KiB ROM)
@@ -37,6 +38,10 @@ impl Mbc1 {
 }
 
 impl super::Mbc for Mbc1 {
+    fn clone_boxed(&self) -> super::BoxedMbc {
+        Box::new(self.clone())
+    }
+
     fn write(&mut self, addr: u16, value: u8) {
         match addr {
             // Enable or disable RAM
@@ -74,17 +79,7 @@ impl super::Mbc for Mbc1 {
             0x0000..=0x3FFF => rom[addr as usize],
             // ROM bank
             0x4000..=0x7FFF => {
-                let mut rom_bank_num = if self.bank_mode == 1 {
-                    // 7 bits
-                    self.bank_num & 0x7F
-                } else {
-                    // 5 bits
-                    self.bank_num & 0x1F
-                };
-                if rom_bank_num == 0 {
-                    rom_bank_num = 1; // Bank 0 is the fixed ROM.
-                }
-                let rom_offset = rom_bank_num * kib(16) + (addr - 0x4000) as usize;
+                let rom_offset = self.rom_bank() * kib(16) + (addr - 0x4000) as usize;
                 rom[rom_offset]
             }
             // RAM bank
@@ -100,6 +95,34 @@ impl super::Mbc for Mbc1 {
         }
     }
 
+    fn ram_bank(&self) -> Option<usize> {
+        let ram_bank_num = (self.bank_num >> 5) & 0b11;
+        (self.ram_enabled && ram_bank_num < self.ram_banks.len()).then_some(ram_bank_num)
+    }
+
+    fn ram_banks(&self) -> &[Box<RamBank>] {
+        &self.ram_banks
+    }
+
+    fn ram_banks_mut(&mut self) -> &mut [Box<RamBank>] {
+        &mut self.ram_banks
+    }
+
+    fn rom_bank(&self) -> usize {
+        let rom_bank_num = if self.bank_mode == 1 {
+            // 7 bits
+            self.bank_num & 0x7F
+        } else {
+            // 5 bits
+            self.bank_num & 0x1F
+        };
+        if rom_bank_num == 0 {
+            1 // Bank 0 is the fixed ROM.
+        } else {
+            rom_bank_num
+        }
+    }
+
     fn suspend(&self) -> Option<Vec<u8>> {
         if self.with_battery {
             let mut data = vec![];
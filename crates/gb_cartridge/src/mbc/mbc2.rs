@@ -1,9 +1,10 @@
-use super::Mbc;
+use super::{BoxedMbc, Mbc};
 use crate::CartridgeHeader;
 use gb_shared::{box_array, is_bit_set, kib, Snapshot};
 use serde::{Deserialize, Serialize};
 
 /// Max 256 KiB ROM, 512x4 bits RAM
+#[derive(Clone)]
 pub(crate) struct Mbc2 {
     ram_enabled: bool,
     /// Value range 0x01..=0x0F
@@ -22,6 +23,10 @@ impl Mbc2 {
 }
 
 impl Mbc for Mbc2 {
+    fn clone_boxed(&self) -> BoxedMbc {
+        Box::new(self.clone())
+    }
+
     fn write(&mut self, addr: u16, value: u8) {
         match addr {
             0x0000..=0x3FFF => {
@@ -48,11 +53,7 @@ impl Mbc for Mbc2 {
         match addr {
             0x0000..=0x3FFF => rom[addr as usize],
             0x4000..=0x7FFF => {
-                let mut rom_bank_num = self.rom_bank_num as usize;
-                if rom_bank_num == 0 {
-                    rom_bank_num = 1;
-                }
-                let rom_addr = rom_bank_num * kib(16) + (addr - 0x4000) as usize;
+                let rom_addr = self.rom_bank() * kib(16) + (addr - 0x4000) as usize;
                 rom[rom_addr]
             }
             0xA000..=0xBFFF => {
@@ -66,6 +67,11 @@ impl Mbc for Mbc2 {
         }
     }
 
+    fn rom_bank(&self) -> usize {
+        // Bank 0 is the fixed ROM, selecting it will select bank 1.
+        (self.rom_bank_num as usize).max(1)
+    }
+
     fn suspend(&self) -> Option<Vec<u8>> {
         if self.with_battery {
             let mut data = vec![];
@@ -1,9 +1,10 @@
-use super::{real_ram_size, Mbc, RamBank};
+use super::{real_ram_size, BoxedMbc, Mbc, RamBank};
 use crate::CartridgeHeader;
 use gb_shared::{box_array, kib, ByteView, Snapshot};
 use serde::{Deserialize, Serialize};
 use web_time::SystemTime;
 
+#[derive(Clone)]
 pub(crate) struct Mbc3 {
     ram_banks: Vec<Box<RamBank>>,
     rtc: RealTimeClock,
@@ -52,6 +53,10 @@ impl Mbc3 {
 }
 
 impl Mbc for Mbc3 {
+    fn clone_boxed(&self) -> BoxedMbc {
+        Box::new(self.clone())
+    }
+
     fn write(&mut self, addr: u16, value: u8) {
         match addr {
             0x0000..=0x1FFF => {
@@ -94,13 +99,7 @@ impl Mbc for Mbc3 {
     fn read(&self, addr: u16, rom: &[u8]) -> u8 {
         match addr {
             0x0000..=0x3FFF => rom[addr as usize],
-            0x4000..=0x7FFF => {
-                let mut rom_bank_num = self.rom_bank_num as usize;
-                if rom_bank_num == 0 {
-                    rom_bank_num = 1;
-                }
-                rom[rom_bank_num * kib(16) + (addr as usize - 0x4000)]
-            }
+            0x4000..=0x7FFF => rom[self.rom_bank() * kib(16) + (addr as usize - 0x4000)],
             0xA000..=0xBFFF => {
                 if !self.ram_rtc_enabled {
                     return 0xFF;
@@ -123,6 +122,25 @@ impl Mbc for Mbc3 {
         }
     }
 
+    fn ram_bank(&self) -> Option<usize> {
+        // RTC registers are not RAM.
+        let ram_bank_num = self.reg_ram_bank_rtc as usize;
+        (self.ram_rtc_enabled && ram_bank_num < self.ram_banks.len()).then_some(ram_bank_num)
+    }
+
+    fn ram_banks(&self) -> &[Box<RamBank>] {
+        &self.ram_banks
+    }
+
+    fn ram_banks_mut(&mut self) -> &mut [Box<RamBank>] {
+        &mut self.ram_banks
+    }
+
+    fn rom_bank(&self) -> usize {
+        // Bank 0 is the fixed ROM, selecting it will select bank 1.
+        (self.rom_bank_num as usize).max(1)
+    }
+
     fn suspend(&self) -> Option<Vec<u8>> {
         if self.with_battery {
             let mut data = vec![];
@@ -1,9 +1,10 @@
 use gb_shared::{box_array, kib, Snapshot};
 use serde::{Deserialize, Serialize};
 
-use super::{real_ram_size, Mbc, RamBank};
+use super::{real_ram_size, BoxedMbc, Mbc, RamBank};
 use crate::CartridgeHeader;
 
+#[derive(Clone)]
 pub(crate) struct Mbc5 {
     /// Only lower 9 bits are used. So its' value is in range of
     /// 0x0000..=0x01FF.
@@ -58,6 +59,10 @@ impl Mbc5 {
 }
 
 impl Mbc for Mbc5 {
+    fn clone_boxed(&self) -> BoxedMbc {
+        Box::new(self.clone())
+    }
+
     fn write(&mut self, addr: u16, value: u8) {
         match addr {
             0x0000..=0x1FFF => {
@@ -89,10 +94,7 @@ impl Mbc for Mbc5 {
     fn read(&self, addr: u16, rom: &[u8]) -> u8 {
         match addr {
             0x0000..=0x3FFF => rom[addr as usize],
-            0x4000..=0x7FFF => {
-                let rom_bank_num = self.rom_bank_num as usize;
-                rom[rom_bank_num * kib(16) + (addr as usize - 0x4000)]
-            }
+            0x4000..=0x7FFF => rom[self.rom_bank() * kib(16) + (addr as usize - 0x4000)],
             0xA000..=0xBFFF => {
                 if !self.ram_enabled {
                     return 0xFF;
@@ -104,6 +106,24 @@ impl Mbc for Mbc5 {
         }
     }
 
+    fn ram_bank(&self) -> Option<usize> {
+        let ram_bank_num = self.ram_bank_num();
+        (self.ram_enabled && ram_bank_num < self.ram_banks.len()).then_some(ram_bank_num)
+    }
+
+    fn ram_banks(&self) -> &[Box<RamBank>] {
+        &self.ram_banks
+    }
+
+    fn ram_banks_mut(&mut self) -> &mut [Box<RamBank>] {
+        &mut self.ram_banks
+    }
+
+    fn rom_bank(&self) -> usize {
+        // Unlike other MBCs, bank 0 can be mapped.
+        self.rom_bank_num as usize
+    }
+
     fn suspend(&self) -> Option<Vec<u8>> {
         if self.with_battery {
             let mut data = vec![];
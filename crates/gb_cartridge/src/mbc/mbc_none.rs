@@ -1,5 +1,6 @@
 use gb_shared::Snapshot;
 
+#[derive(Clone)]
 pub(crate) struct MbcNone {}
 
 impl MbcNone {
@@ -9,6 +10,10 @@ impl MbcNone {
 }
 
 impl super::Mbc for MbcNone {
+    fn clone_boxed(&self) -> super::BoxedMbc {
+        Box::new(self.clone())
+    }
+
     fn write(&mut self, _addr: u16, _value: u8) {
         // Noop
     }
@@ -9,9 +9,28 @@ pub(crate) mod mbc_none;
 /// The size of each RAM bank is 8KiB
 type RamBank = [u8; 0x2000];
 
-pub(crate) trait Mbc: Snapshot {
+pub(crate) type BoxedMbc = Box<dyn Mbc<Snapshot = Vec<u8>>>;
+
+pub(crate) trait Mbc: Snapshot + Send {
+    /// Trait objects can't be cloned directly.
+    fn clone_boxed(&self) -> BoxedMbc;
     fn write(&mut self, addr: u16, value: u8);
     fn read(&self, addr: u16, rom: &[u8]) -> u8;
+    /// ROM bank mapped at [0x4000, 0x7FFF].
+    fn rom_bank(&self) -> usize {
+        1
+    }
+    /// RAM bank mapped at [0xA000, 0xBFFF], if it's a bank in
+    /// [`Mbc::ram_banks`] which can be accessed directly.
+    fn ram_bank(&self) -> Option<usize> {
+        None
+    }
+    fn ram_banks(&self) -> &[Box<RamBank>] {
+        &[]
+    }
+    fn ram_banks_mut(&mut self) -> &mut [Box<RamBank>] {
+        &mut []
+    }
     /// For battery-backed cartridge.
     fn suspend(&self) -> Option<Vec<u8>> {
         None
@@ -23,6 +42,12 @@ pub(crate) trait Mbc: Snapshot {
     }
 }
 
+impl Clone for BoxedMbc {
+    fn clone(&self) -> Self {
+        self.clone_boxed()
+    }
+}
+
 /// https://gbdev.io/pandocs/The_Cartridge_Header.html#0149--ram-size
 pub(crate) fn real_ram_size(ram_size: u8) -> usize {
     match ram_size {
@@ -0,0 +1,730 @@
+//! SM83 disassembler.
+//!
+//! Decode an instruction into its mnemonic, operands, length, cycles and
+//! the flags it affects. Both unprefixed and `0xCB` prefixed opcodes are
+//! supported.
+//!
+//! @see https://gbdev.io/gb-opcodes/optables/
+use gb_shared::Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg8 {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg16 {
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg8(Reg8),
+    Reg16(Reg16),
+    /// (BC), (DE), (HL)
+    Indirect(Reg16),
+    /// (HL+)
+    IndirectHLInc,
+    /// (HL-)
+    IndirectHLDec,
+    /// (C), i.e. (0xFF00+C)
+    IndirectC,
+    /// d8
+    Imm8(u8),
+    /// d16
+    Imm16(u16),
+    /// (a16)
+    Address(u16),
+    /// (a8), i.e. (0xFF00+a8)
+    HighAddress(u8),
+    /// r8 of relative jumps
+    Relative(i8),
+    /// r8 of ADD SP,r8
+    Offset(i8),
+    /// SP+r8
+    SPOffset(i8),
+    Condition(Condition),
+    /// Bit index of BIT, RES and SET
+    Bit(u8),
+    /// Target address of RST
+    Vector(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagEffect {
+    Unaffected,
+    Reset,
+    Set,
+    /// Depends on the result.
+    Affected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    pub z: FlagEffect,
+    pub n: FlagEffect,
+    pub h: FlagEffect,
+    pub c: FlagEffect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// Opcode. Prefixed opcodes are in the form of `0xCBxx`.
+    pub opcode: u16,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    /// Length in bytes, including opcode and immediate data.
+    pub len: u8,
+    /// T-cycles. For conditional instructions, it's the cycles when
+    /// the branch is taken.
+    pub cycles: u8,
+    /// T-cycles when the branch is not taken. `None` for unconditional
+    /// instructions.
+    pub cycles_not_taken: Option<u8>,
+    pub flags: Flags,
+}
+
+impl Instruction {
+    #[inline]
+    pub fn conditional(&self) -> bool {
+        self.cycles_not_taken.is_some()
+    }
+
+    /// Illegal opcodes are decoded into pseudo instruction `DB`,
+    /// which hangs the CPU.
+    #[inline]
+    pub fn illegal(&self) -> bool {
+        self.mnemonic == "DB"
+    }
+}
+
+/// Decode the instruction at `addr`.
+pub fn decode<M: Memory + ?Sized>(memory: &M, addr: u16) -> Instruction {
+    let bytes =
+        [memory.read(addr), memory.read(addr.wrapping_add(1)), memory.read(addr.wrapping_add(2))];
+
+    decode_raw(bytes)
+}
+
+/// Decode the instruction at the start of `bytes`. Return `None`
+/// if `bytes` is too short to hold the whole instruction.
+pub fn decode_bytes(bytes: &[u8]) -> Option<Instruction> {
+    let mut raw = [0; 3];
+    let n = bytes.len().min(3);
+    raw[..n].copy_from_slice(&bytes[..n]);
+
+    let inst = decode_raw(raw);
+    if (inst.len as usize) > bytes.len() {
+        return None;
+    }
+
+    Some(inst)
+}
+
+/// Parse flag effects in the form of opcode tables, e.g. `Z0H-`.
+const fn flags(spec: &[u8; 4]) -> Flags {
+    const fn effect(c: u8) -> FlagEffect {
+        match c {
+            b'-' => FlagEffect::Unaffected,
+            b'0' => FlagEffect::Reset,
+            b'1' => FlagEffect::Set,
+            _ => FlagEffect::Affected,
+        }
+    }
+
+    Flags { z: effect(spec[0]), n: effect(spec[1]), h: effect(spec[2]), c: effect(spec[3]) }
+}
+
+const NONE: Flags = flags(b"----");
+
+/// Operand of the 3-bit register index, where 6 is (HL).
+fn r(index: u8) -> Operand {
+    match index & 0b111 {
+        0 => Operand::Reg8(Reg8::B),
+        1 => Operand::Reg8(Reg8::C),
+        2 => Operand::Reg8(Reg8::D),
+        3 => Operand::Reg8(Reg8::E),
+        4 => Operand::Reg8(Reg8::H),
+        5 => Operand::Reg8(Reg8::L),
+        6 => Operand::Indirect(Reg16::HL),
+        7 => Operand::Reg8(Reg8::A),
+        _ => unreachable!(),
+    }
+}
+
+fn rp(index: u8) -> Reg16 {
+    match index & 0b11 {
+        0 => Reg16::BC,
+        1 => Reg16::DE,
+        2 => Reg16::HL,
+        3 => Reg16::SP,
+        _ => unreachable!(),
+    }
+}
+
+fn rp2(index: u8) -> Reg16 {
+    match index & 0b11 {
+        0 => Reg16::BC,
+        1 => Reg16::DE,
+        2 => Reg16::HL,
+        3 => Reg16::AF,
+        _ => unreachable!(),
+    }
+}
+
+fn cc(index: u8) -> Condition {
+    match index & 0b11 {
+        0 => Condition::NZ,
+        1 => Condition::Z,
+        2 => Condition::NC,
+        3 => Condition::C,
+        _ => unreachable!(),
+    }
+}
+
+fn alu(index: u8) -> (&'static str, Flags) {
+    match index & 0b111 {
+        0 => ("ADD", flags(b"Z0HC")),
+        1 => ("ADC", flags(b"Z0HC")),
+        2 => ("SUB", flags(b"Z1HC")),
+        3 => ("SBC", flags(b"Z1HC")),
+        4 => ("AND", flags(b"Z010")),
+        5 => ("XOR", flags(b"Z000")),
+        6 => ("OR", flags(b"Z000")),
+        7 => ("CP", flags(b"Z1HC")),
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn decode_raw(bytes: [u8; 3]) -> Instruction {
+    let opcode = bytes[0];
+    let d8 = bytes[1];
+    let d16 = u16::from_le_bytes([bytes[1], bytes[2]]);
+
+    if opcode == 0xCB {
+        return decode_cb(d8);
+    }
+
+    // Opcodes are decoded in octal form `xxyyyzzz`.
+    // @see https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 0b111;
+    let z = opcode & 0b111;
+    let p = y >> 1;
+    let q = y & 1;
+    let hl = |index: u8| index == 6;
+
+    let inst = |mnemonic, operands: Vec<Operand>, len, cycles, flags| Instruction {
+        opcode: opcode as u16,
+        mnemonic,
+        operands,
+        len,
+        cycles,
+        cycles_not_taken: None,
+        flags,
+    };
+    let branch = |mnemonic, operands: Vec<Operand>, len, cycles, cycles_not_taken| Instruction {
+        opcode: opcode as u16,
+        mnemonic,
+        operands,
+        len,
+        cycles,
+        cycles_not_taken: Some(cycles_not_taken),
+        flags: NONE,
+    };
+    let illegal = || inst("DB", vec![Operand::Imm8(opcode)], 1, 0, NONE);
+
+    use Operand::*;
+    match (x, z) {
+        (0, 0) => match y {
+            0 => inst("NOP", vec![], 1, 4, NONE),
+            1 => inst("LD", vec![Address(d16), Reg16(self::Reg16::SP)], 3, 20, NONE),
+            // STOP is followed by a padding byte.
+            2 => inst("STOP", vec![], 2, 4, NONE),
+            3 => inst("JR", vec![Relative(d8 as i8)], 2, 12, NONE),
+            _ => branch("JR", vec![Condition(cc(y - 4)), Relative(d8 as i8)], 2, 12, 8),
+        },
+        (0, 1) => {
+            if q == 0 {
+                inst("LD", vec![Reg16(rp(p)), Imm16(d16)], 3, 12, NONE)
+            } else {
+                inst("ADD", vec![Reg16(self::Reg16::HL), Reg16(rp(p))], 1, 8, flags(b"-0HC"))
+            }
+        }
+        (0, 2) => {
+            let mem = match p {
+                0 => Indirect(self::Reg16::BC),
+                1 => Indirect(self::Reg16::DE),
+                2 => IndirectHLInc,
+                3 => IndirectHLDec,
+                _ => unreachable!(),
+            };
+            let operands = if q == 0 {
+                vec![mem, Reg8(self::Reg8::A)]
+            } else {
+                vec![Reg8(self::Reg8::A), mem]
+            };
+            inst("LD", operands, 1, 8, NONE)
+        }
+        (0, 3) => inst(if q == 0 { "INC" } else { "DEC" }, vec![Reg16(rp(p))], 1, 8, NONE),
+        (0, 4) => inst("INC", vec![r(y)], 1, if hl(y) { 12 } else { 4 }, flags(b"Z0H-")),
+        (0, 5) => inst("DEC", vec![r(y)], 1, if hl(y) { 12 } else { 4 }, flags(b"Z1H-")),
+        (0, 6) => inst("LD", vec![r(y), Imm8(d8)], 2, if hl(y) { 12 } else { 8 }, NONE),
+        (0, 7) => match y {
+            0 => inst("RLCA", vec![], 1, 4, flags(b"000C")),
+            1 => inst("RRCA", vec![], 1, 4, flags(b"000C")),
+            2 => inst("RLA", vec![], 1, 4, flags(b"000C")),
+            3 => inst("RRA", vec![], 1, 4, flags(b"000C")),
+            4 => inst("DAA", vec![], 1, 4, flags(b"Z-0C")),
+            5 => inst("CPL", vec![], 1, 4, flags(b"-11-")),
+            6 => inst("SCF", vec![], 1, 4, flags(b"-001")),
+            7 => inst("CCF", vec![], 1, 4, flags(b"-00C")),
+            _ => unreachable!(),
+        },
+        (1, _) => {
+            if hl(y) && hl(z) {
+                inst("HALT", vec![], 1, 4, NONE)
+            } else {
+                inst("LD", vec![r(y), r(z)], 1, if hl(y) || hl(z) { 8 } else { 4 }, NONE)
+            }
+        }
+        (2, _) => {
+            let (mnemonic, flags) = alu(y);
+            inst(mnemonic, vec![Reg8(self::Reg8::A), r(z)], 1, if hl(z) { 8 } else { 4 }, flags)
+        }
+        (3, 0) => match y {
+            0..=3 => branch("RET", vec![Condition(cc(y))], 1, 20, 8),
+            4 => inst("LDH", vec![HighAddress(d8), Reg8(self::Reg8::A)], 2, 12, NONE),
+            5 => inst("ADD", vec![Reg16(self::Reg16::SP), Offset(d8 as i8)], 2, 16, flags(b"00HC")),
+            6 => inst("LDH", vec![Reg8(self::Reg8::A), HighAddress(d8)], 2, 12, NONE),
+            7 => {
+                inst("LD", vec![Reg16(self::Reg16::HL), SPOffset(d8 as i8)], 2, 12, flags(b"00HC"))
+            }
+            _ => unreachable!(),
+        },
+        (3, 1) => {
+            if q == 0 {
+                let reg = rp2(p);
+                let flags = if reg == self::Reg16::AF { flags(b"ZNHC") } else { NONE };
+                inst("POP", vec![Reg16(reg)], 1, 12, flags)
+            } else {
+                match p {
+                    0 => inst("RET", vec![], 1, 16, NONE),
+                    1 => inst("RETI", vec![], 1, 16, NONE),
+                    2 => inst("JP", vec![Reg16(self::Reg16::HL)], 1, 4, NONE),
+                    3 => {
+                        inst("LD", vec![Reg16(self::Reg16::SP), Reg16(self::Reg16::HL)], 1, 8, NONE)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        (3, 2) => match y {
+            0..=3 => branch("JP", vec![Condition(cc(y)), Imm16(d16)], 3, 16, 12),
+            4 => inst("LD", vec![IndirectC, Reg8(self::Reg8::A)], 1, 8, NONE),
+            5 => inst("LD", vec![Address(d16), Reg8(self::Reg8::A)], 3, 16, NONE),
+            6 => inst("LD", vec![Reg8(self::Reg8::A), IndirectC], 1, 8, NONE),
+            7 => inst("LD", vec![Reg8(self::Reg8::A), Address(d16)], 3, 16, NONE),
+            _ => unreachable!(),
+        },
+        (3, 3) => match y {
+            0 => inst("JP", vec![Imm16(d16)], 3, 16, NONE),
+            6 => inst("DI", vec![], 1, 4, NONE),
+            7 => inst("EI", vec![], 1, 4, NONE),
+            // 1 is 0xCB prefix, which is handled above.
+            _ => illegal(),
+        },
+        (3, 4) => match y {
+            0..=3 => branch("CALL", vec![Condition(cc(y)), Imm16(d16)], 3, 24, 12),
+            _ => illegal(),
+        },
+        (3, 5) => {
+            if q == 0 {
+                inst("PUSH", vec![Reg16(rp2(p))], 1, 16, NONE)
+            } else if p == 0 {
+                inst("CALL", vec![Imm16(d16)], 3, 24, NONE)
+            } else {
+                illegal()
+            }
+        }
+        (3, 6) => {
+            let (mnemonic, flags) = alu(y);
+            inst(mnemonic, vec![Reg8(self::Reg8::A), Imm8(d8)], 2, 8, flags)
+        }
+        (3, 7) => inst("RST", vec![Vector(y * 8)], 1, 16, NONE),
+        _ => unreachable!(),
+    }
+}
+
+fn decode_cb(opcode: u8) -> Instruction {
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 0b111;
+    let z = opcode & 0b111;
+    let hl = z == 6;
+
+    let (mnemonic, operands, cycles, flags) = match x {
+        0 => {
+            let (mnemonic, flags) = match y {
+                0 => ("RLC", flags(b"Z00C")),
+                1 => ("RRC", flags(b"Z00C")),
+                2 => ("RL", flags(b"Z00C")),
+                3 => ("RR", flags(b"Z00C")),
+                4 => ("SLA", flags(b"Z00C")),
+                5 => ("SRA", flags(b"Z00C")),
+                6 => ("SWAP", flags(b"Z000")),
+                7 => ("SRL", flags(b"Z00C")),
+                _ => unreachable!(),
+            };
+            (mnemonic, vec![r(z)], if hl { 16 } else { 8 }, flags)
+        }
+        // BIT only reads (HL), so it takes less cycles.
+        1 => ("BIT", vec![Operand::Bit(y), r(z)], if hl { 12 } else { 8 }, flags(b"Z01-")),
+        2 => ("RES", vec![Operand::Bit(y), r(z)], if hl { 16 } else { 8 }, NONE),
+        3 => ("SET", vec![Operand::Bit(y), r(z)], if hl { 16 } else { 8 }, NONE),
+        _ => unreachable!(),
+    };
+
+    Instruction {
+        opcode: 0xCB00 | (opcode as u16),
+        mnemonic,
+        operands,
+        len: 2,
+        cycles,
+        cycles_not_taken: None,
+        flags,
+    }
+}
+
+impl core::fmt::Display for Reg8 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl core::fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl core::fmt::Display for Condition {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl core::fmt::Display for Operand {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Operand::Reg8(reg) => write!(f, "{}", reg),
+            Operand::Reg16(reg) => write!(f, "{}", reg),
+            Operand::Indirect(reg) => write!(f, "({})", reg),
+            Operand::IndirectHLInc => write!(f, "(HL+)"),
+            Operand::IndirectHLDec => write!(f, "(HL-)"),
+            Operand::IndirectC => write!(f, "(C)"),
+            Operand::Imm8(value) => write!(f, "${:02X}", value),
+            Operand::Imm16(value) => write!(f, "${:04X}", value),
+            Operand::Address(addr) => write!(f, "(${:04X})", addr),
+            Operand::HighAddress(addr) => write!(f, "($FF{:02X})", addr),
+            Operand::Relative(offset) | Operand::Offset(offset) => write!(f, "{:+}", offset),
+            Operand::SPOffset(offset) => write!(f, "SP{:+}", offset),
+            Operand::Condition(cond) => write!(f, "{}", cond),
+            Operand::Bit(bit) => write!(f, "{}", bit),
+            Operand::Vector(addr) => write!(f, "${:02X}", addr),
+        }
+    }
+}
+
+impl core::fmt::Display for Flags {
+    /// In the form of opcode tables, e.g. `Z0H-`.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (effect, name) in [(self.z, 'Z'), (self.n, 'N'), (self.h, 'H'), (self.c, 'C')] {
+            let c = match effect {
+                FlagEffect::Unaffected => '-',
+                FlagEffect::Reset => '0',
+                FlagEffect::Set => '1',
+                FlagEffect::Affected => name,
+            };
+            write!(f, "{}", c)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl core::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { "," }, operand)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, RamBus};
+
+    /// Lengths of unprefixed opcodes, 0 for illegal ones.
+    #[rustfmt::skip]
+    const LENGTHS: [u8; 256] = [
+        1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,
+        1, 1, 3, 0, 3, 1, 2, 1, 1, 1, 3, 0, 3, 0, 2, 1,
+        2, 1, 1, 0, 0, 1, 2, 1, 2, 1, 3, 0, 0, 0, 2, 1,
+        2, 1, 1, 1, 0, 1, 2, 1, 2, 1, 3, 1, 0, 0, 2, 1,
+    ];
+
+    /// M-cycles of unprefixed opcodes, branches not taken.
+    /// @see https://github.com/retrio/gb-test-roms/blob/master/instr_timing/source/instr_timing.s
+    #[rustfmt::skip]
+    const CYCLES: [u8; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    /// M-cycles of conditional opcodes when branches are taken.
+    const TAKEN_CYCLES: [(u8, u8); 16] = [
+        (0x20, 3),
+        (0x28, 3),
+        (0x30, 3),
+        (0x38, 3),
+        (0xC0, 5),
+        (0xC8, 5),
+        (0xD0, 5),
+        (0xD8, 5),
+        (0xC2, 4),
+        (0xCA, 4),
+        (0xD2, 4),
+        (0xDA, 4),
+        (0xC4, 6),
+        (0xCC, 6),
+        (0xD4, 6),
+        (0xDC, 6),
+    ];
+
+    const ILLEGAL_OPCODES: [u8; 11] =
+        [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+    #[test]
+    fn unprefixed_lengths_and_cycles() {
+        for opcode in 0..=0xFFu8 {
+            if opcode == 0xCB {
+                continue;
+            }
+
+            let inst = decode_raw([opcode, 0, 0]);
+            let index = opcode as usize;
+            if ILLEGAL_OPCODES.contains(&opcode) {
+                assert!(inst.illegal(), "{:#04X}", opcode);
+                continue;
+            }
+
+            assert!(!inst.illegal(), "{:#04X}", opcode);
+            assert_eq!(inst.opcode, opcode as u16);
+            assert_eq!(inst.len, LENGTHS[index], "length of {:#04X} {}", opcode, inst);
+
+            match TAKEN_CYCLES.iter().find(|(op, _)| *op == opcode) {
+                Some((_, taken)) => {
+                    assert_eq!(inst.cycles, taken * 4, "cycles of {:#04X} {}", opcode, inst);
+                    assert_eq!(
+                        inst.cycles_not_taken,
+                        Some(CYCLES[index] * 4),
+                        "cycles of {:#04X} {}",
+                        opcode,
+                        inst
+                    );
+                }
+                None => {
+                    assert_eq!(
+                        inst.cycles,
+                        CYCLES[index] * 4,
+                        "cycles of {:#04X} {}",
+                        opcode,
+                        inst
+                    );
+                    assert_eq!(inst.cycles_not_taken, None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prefixed_lengths_and_cycles() {
+        for opcode in 0..=0xFFu8 {
+            let inst = decode_raw([0xCB, opcode, 0]);
+            let cycles = match (opcode & 0b111, opcode >> 6) {
+                (6, 1) => 12,
+                (6, _) => 16,
+                _ => 8,
+            };
+
+            assert_eq!(inst.opcode, 0xCB00 | opcode as u16);
+            assert_eq!(inst.len, 2);
+            assert_eq!(inst.cycles, cycles, "cycles of {:#06X} {}", inst.opcode, inst);
+            assert_eq!(inst.cycles_not_taken, None);
+        }
+    }
+
+    #[test]
+    fn format_instructions() {
+        let cases: &[(&[u8], &str, &str)] = &[
+            (&[0x00], "NOP", "----"),
+            (&[0x01, 0x34, 0x12], "LD BC,$1234", "----"),
+            (&[0x08, 0x00, 0xC0], "LD ($C000),SP", "----"),
+            (&[0x18, 0xFE], "JR -2", "----"),
+            (&[0x20, 0x05], "JR NZ,+5", "----"),
+            (&[0x22], "LD (HL+),A", "----"),
+            (&[0x3A], "LD A,(HL-)", "----"),
+            (&[0x27], "DAA", "Z-0C"),
+            (&[0x34], "INC (HL)", "Z0H-"),
+            (&[0x76], "HALT", "----"),
+            (&[0x7E], "LD A,(HL)", "----"),
+            (&[0x96], "SUB A,(HL)", "Z1HC"),
+            (&[0xAF], "XOR A,A", "Z000"),
+            (&[0xE0, 0x40], "LDH ($FF40),A", "----"),
+            (&[0xE2], "LD (C),A", "----"),
+            (&[0xE8, 0xFF], "ADD SP,-1", "00HC"),
+            (&[0xE8, 0x10], "ADD SP,+16", "00HC"),
+            (&[0xF1], "POP AF", "ZNHC"),
+            (&[0xF8, 0x02], "LD HL,SP+2", "00HC"),
+            (&[0xF8, 0xFE], "LD HL,SP-2", "00HC"),
+            (&[0xFF], "RST $38", "----"),
+            (&[0xD3], "DB $D3", "----"),
+            (&[0xCB, 0x37], "SWAP A", "Z000"),
+            (&[0xCB, 0x7E], "BIT 7,(HL)", "Z01-"),
+            (&[0xCB, 0x80], "RES 0,B", "----"),
+        ];
+
+        for (bytes, text, flags) in cases {
+            let inst = decode_bytes(bytes).unwrap();
+            assert_eq!(&inst.to_string(), text);
+            assert_eq!(&inst.flags.to_string(), flags);
+            assert_eq!(inst.len as usize, bytes.len());
+        }
+    }
+
+    #[test]
+    fn decode_bytes_too_short() {
+        assert!(decode_bytes(&[]).is_none());
+        assert!(decode_bytes(&[0x01, 0x34]).is_none());
+        assert!(decode_bytes(&[0xCB]).is_none());
+        assert!(decode_bytes(&[0x00]).is_some());
+    }
+
+    #[test]
+    fn decode_from_memory() {
+        let mut bus = RamBus::new();
+        bus.ram[0xFFFF] = 0xC3;
+        bus.ram[0x0000] = 0x50;
+        bus.ram[0x0001] = 0x01;
+
+        let inst = decode(&bus, 0xFFFF);
+        assert_eq!(inst.to_string(), "JP $0150");
+    }
+
+    /// Execute every opcode with the interpreter, and check the cycles
+    /// and length against the disassembler.
+    #[test]
+    fn agree_with_interpreter() {
+        const PC: u16 = 0xC000;
+        let skipped = [0x10, 0x76, 0xCB];
+
+        let run = |bytes: [u8; 3], flags: u8| {
+            let mut bus = RamBus::new();
+            bus.ram[PC as usize..PC as usize + 3].copy_from_slice(&bytes);
+
+            let mut cpu = Cpu::new(bus);
+            cpu.sp = 0xD000;
+            cpu.reg_f = flags;
+            cpu.set_hl(0xD100);
+            cpu.ir = bytes[0];
+            cpu.pc = PC + 1;
+            cpu.step();
+
+            (cpu.take_clocks(), cpu.pc)
+        };
+
+        for opcode in 0..=0xFFu8 {
+            if skipped.contains(&opcode) || ILLEGAL_OPCODES.contains(&opcode) {
+                continue;
+            }
+
+            // Operands point to the instruction itself, so that jumps taken
+            // can be distinguished from not taken.
+            let bytes = [opcode, 0xFF, 0xFF];
+            let inst = decode_raw(bytes);
+            for flags in [0x00, 0xF0] {
+                let (clocks, pc) = run(bytes, flags);
+                // PC points to the byte after the prefetched opcode.
+                let next_pc = PC + inst.len as u16 + 1;
+                if pc == next_pc {
+                    assert_eq!(
+                        Some(clocks),
+                        inst.cycles_not_taken.or(Some(inst.cycles)),
+                        "{:#04X} {}",
+                        opcode,
+                        inst
+                    );
+                } else {
+                    assert_eq!(clocks, inst.cycles, "{:#04X} {}", opcode, inst);
+                }
+            }
+        }
+
+        for opcode in 0..=0xFFu8 {
+            let bytes = [0xCB, opcode, 0];
+            let inst = decode_raw(bytes);
+            let (clocks, pc) = run(bytes, 0);
+
+            assert_eq!(clocks, inst.cycles, "{:#06X} {}", inst.opcode, inst);
+            assert_eq!(pc, PC + inst.len as u16 + 1);
+        }
+    }
+}
@@ -1,20 +1,29 @@
+pub mod disasm;
 mod inst;
 mod interrupt;
+pub mod trace;
 
-use gb_shared::{is_bit_set, set_bits, unset_bits, ByteView, MachineModel, Snapshot};
+use gb_shared::{
+    is_bit_set, set_bits, unset_bits, ByteView, HardwareModel, InterruptType, MachineModel,
+    Snapshot,
+};
 use interrupt::INTERRUPTS;
+use trace::{RichTrace, TraceMode, TraceRecord, TraceSink, Tracer};
 
 impl<BUS> Cpu<BUS>
 where
     BUS: gb_shared::Bus,
 {
     fn adv_clocks(&mut self, clocks: u8) {
-        self.clocks = self.clocks.wrapping_add(clocks);
+        // `clocks` are counted in real-time, which are half of CPU clocks
+        // in double speed mode.
+        let real_clocks = if self.double_speed { clocks / 2 } else { clocks };
+        self.clocks = self.clocks.wrapping_add(real_clocks);
         self.bus.step(clocks);
     }
 
     fn bus_read(&mut self, addr: u16) -> u8 {
-        let value = self.bus.read(addr);
+        let value = self.bus.cpu_read(addr);
         self.adv_clocks(4);
 
         value
@@ -82,11 +91,40 @@ where
         self.pc = self.pc.wrapping_add_signed(r8 as i16);
     }
 
-    fn set_ime(&mut self, enabled: bool) {
-        self.ime = enabled;
-    }
-
+    /// @see https://gbdev.io/pandocs/Reducing_Power_Consumption.html#using-the-stop-instruction
     fn stop(&mut self) {
+        let button_held = self.bus.read(0xFF00) & 0x0F != 0x0F;
+        let itr_pending = self.itr_pending();
+
+        if button_held {
+            // DIV is not reset. If an interrupt is pending, STOP is a 1-byte
+            // opcode and nothing happens. Otherwise, STOP is a 2-byte opcode
+            // and HALT mode is entered.
+            if !itr_pending {
+                self.pc = self.pc.wrapping_add(1);
+                self.halted = true;
+                self.handle_itr = true;
+            }
+            return;
+        }
+
+        if !itr_pending {
+            self.pc = self.pc.wrapping_add(1);
+        }
+        self.bus.write(0xFF04, 0);
+
+        if self.machine_model == MachineModel::CGB && is_bit_set!(self.bus.read(0xFF4D), 0) {
+            // Speed switch is armed via KEY1.
+            // @see https://gbdev.io/pandocs/CGB_Registers.html#ff4d--key1-cgb-mode-only-prepare-speed-switch
+            if itr_pending && self.ime {
+                log::warn!("Speed switch with interrupt pending and IME set is undefined");
+            }
+            self.double_speed = !self.double_speed;
+            self.bus.set_double_speed(self.double_speed);
+            self.speed_switch_cycles = SPEED_SWITCH_CYCLES;
+            return;
+        }
+
         self.stopped = true;
     }
 
@@ -139,8 +177,13 @@ where
     /// instructions, but the jump won't be performed(and the IF flag
     /// won't be cleared).
     halted: bool,
-    /// Set by instruction STOP
+    /// Set by instruction STOP. CPU, LCD and other components are paused,
+    /// until a selected joypad line goes low.
     stopped: bool,
+    /// CPU is running in double speed mode(CGB only).
+    double_speed: bool,
+    /// Remaining M-cycles the CPU stays paused for the speed switch.
+    speed_switch_cycles: u16,
 
     pub(crate) clocks: u8,
 
@@ -155,6 +198,50 @@ where
     /// Get read in HALT mode only.
     handle_itr: bool,
     machine_model: MachineModel,
+    /// Whether the opcode of next instruction was fetched in last step,
+    /// i.e. CPU stops at an instruction boundary.
+    fetched: bool,
+    /// The interrupt serviced in last step.
+    serviced_itr: Option<InterruptType>,
+    /// Real-time clocks taken so far, used by trace.
+    elapsed_clocks: u64,
+    tracer: Option<Tracer>,
+}
+
+/// The trace sink is not cloned.
+impl<BUS> Clone for Cpu<BUS>
+where
+    BUS: gb_shared::Bus + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            reg_a: self.reg_a,
+            reg_f: self.reg_f,
+            reg_b: self.reg_b,
+            reg_c: self.reg_c,
+            reg_d: self.reg_d,
+            reg_e: self.reg_e,
+            reg_h: self.reg_h,
+            reg_l: self.reg_l,
+            sp: self.sp,
+            pc: self.pc,
+            ime: self.ime,
+            enabling_ime: self.enabling_ime,
+            halted: self.halted,
+            stopped: self.stopped,
+            double_speed: self.double_speed,
+            speed_switch_cycles: self.speed_switch_cycles,
+            clocks: self.clocks,
+            bus: self.bus.clone(),
+            ir: self.ir,
+            handle_itr: self.handle_itr,
+            machine_model: self.machine_model,
+            fetched: self.fetched,
+            serviced_itr: self.serviced_itr,
+            elapsed_clocks: self.elapsed_clocks,
+            tracer: None,
+        }
+    }
 }
 
 impl<BUS> core::fmt::Debug for Cpu<BUS>
@@ -176,6 +263,9 @@ where
     }
 }
 
+/// Speed switch takes about 2050 M-cycles, during which the CPU is paused.
+const SPEED_SWITCH_CYCLES: u16 = 2050;
+
 #[inline]
 fn convert_u8_tuple_to_u16(hi: u8, lo: u8) -> u16 {
     ((hi as u16) << 8) | (lo as u16)
@@ -202,32 +292,75 @@ where
             enabling_ime: false,
             halted: false,
             stopped: false,
+            double_speed: false,
+            speed_switch_cycles: 0,
             clocks: 0,
             ir: 0,
             handle_itr: true,
             machine_model: MachineModel::DMG,
+            fetched: false,
+            serviced_itr: None,
+            elapsed_clocks: 0,
+            tracer: None,
             bus,
         }
     }
 
     pub fn new_dmg(bus: BUS, cart_header_checksum: u8) -> Self {
+        Self::new_post_boot(bus, HardwareModel::DMG, MachineModel::DMG, cart_header_checksum)
+    }
+
+    pub fn new_cgb(bus: BUS) -> Self {
+        Self::new_post_boot(bus, HardwareModel::CGBE, MachineModel::CGB, 0)
+    }
+
+    /// Registers left by the boot ROM of `model`, which runs in the mode
+    /// of `machine_model`. Flags on DMG and MGB depend on the cartridge
+    /// header checksum.
+    ///
+    /// @see https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+    pub fn new_post_boot(
+        bus: BUS,
+        model: HardwareModel,
+        machine_model: MachineModel,
+        cart_header_checksum: u8,
+    ) -> Self {
+        let dmg_flags = if cart_header_checksum == 0 { 0x80 } else { 0xB0 };
+        let cgb_mode = machine_model == MachineModel::CGB;
+        // B, H and L in DMG mode on CGB depend on the title checksum of
+        // licensed games, those for others are used.
+        let (af, bc, de, hl) = match model {
+            HardwareModel::DMG0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            HardwareModel::DMG => (0x0100 | dmg_flags, 0x0013, 0x00D8, 0x014D),
+            HardwareModel::MGB => (0xFF00 | dmg_flags, 0x0013, 0x00D8, 0x014D),
+            HardwareModel::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+            HardwareModel::SGB2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            HardwareModel::AGB if cgb_mode => (0x1100, 0x0100, 0xFF56, 0x000D),
+            HardwareModel::AGB => (0x1100, 0x0100, 0x0008, 0x007C),
+            _ if cgb_mode => (0x1180, 0x0000, 0xFF56, 0x000D),
+            _ => (0x1180, 0x0000, 0x0008, 0x007C),
+        };
+
         let mut cpu = Self::new(bus);
-        cpu.set_af(if cart_header_checksum == 0 { 0x0180 } else { 0x01B0 });
-        cpu.set_bc(0x0013);
-        cpu.set_de(0x00D8);
-        cpu.set_hl(0x014D);
-        cpu.machine_model = MachineModel::DMG;
+        cpu.set_af(af);
+        cpu.set_bc(bc);
+        cpu.set_de(de);
+        cpu.set_hl(hl);
+        cpu.machine_model = machine_model;
 
         cpu
     }
 
-    pub fn new_cgb(bus: BUS) -> Self {
+    /// Power-on state, to run the boot ROM from 0x0000.
+    pub fn new_with_boot_rom(bus: BUS, machine_model: MachineModel) -> Self {
         let mut cpu = Self::new(bus);
-        cpu.set_af(0x1180);
-        cpu.set_bc(0x0000);
-        cpu.set_de(0xFF56);
-        cpu.set_hl(0x000D);
-        cpu.machine_model = MachineModel::CGB;
+        cpu.set_af(0);
+        cpu.set_bc(0);
+        cpu.set_de(0);
+        cpu.set_hl(0);
+        cpu.sp = 0;
+        cpu.pc = 0;
+        cpu.machine_model = machine_model;
 
         cpu
     }
@@ -238,7 +371,7 @@ where
     }
 
     #[inline]
-    fn set_af(&mut self, value: u16) {
+    pub fn set_af(&mut self, value: u16) {
         self.reg_a = value.msb();
         self.reg_f = value.lsb() & 0xF0;
     }
@@ -248,7 +381,8 @@ where
         convert_u8_tuple_to_u16(self.reg_b, self.reg_c)
     }
 
-    fn set_bc(&mut self, value: u16) {
+    #[inline]
+    pub fn set_bc(&mut self, value: u16) {
         self.reg_b = value.msb();
         self.reg_c = value.lsb();
     }
@@ -258,7 +392,8 @@ where
         convert_u8_tuple_to_u16(self.reg_d, self.reg_e)
     }
 
-    fn set_de(&mut self, value: u16) {
+    #[inline]
+    pub fn set_de(&mut self, value: u16) {
         self.reg_d = value.msb();
         self.reg_e = value.lsb();
     }
@@ -268,11 +403,98 @@ where
         convert_u8_tuple_to_u16(self.reg_h, self.reg_l)
     }
 
-    fn set_hl(&mut self, value: u16) {
+    #[inline]
+    pub fn set_hl(&mut self, value: u16) {
         self.reg_h = value.msb();
         self.reg_l = value.lsb();
     }
 
+    #[inline]
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    #[inline]
+    pub fn set_sp(&mut self, value: u16) {
+        self.sp = value;
+    }
+
+    /// Address of the next instruction to be executed.
+    ///
+    /// As the opcode is prefetched, the internal PC always points
+    /// to the byte after it.
+    #[inline]
+    pub fn pc(&self) -> u16 {
+        self.pc.wrapping_sub(1)
+    }
+
+    /// Jump to `addr` without consuming any clocks. The opcode at `addr`
+    /// is fetched immediately.
+    pub fn set_pc(&mut self, addr: u16) {
+        self.ir = self.bus.cpu_read(addr);
+        self.pc = addr.wrapping_add(1);
+    }
+
+    #[inline]
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    #[inline]
+    pub fn set_ime(&mut self, enabled: bool) {
+        self.ime = enabled;
+    }
+
+    #[inline]
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    #[inline]
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    #[inline]
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Whether CPU stops at an instruction boundary after last step,
+    /// which means the instruction at [`Cpu::pc`] is about to be executed.
+    /// It's false if last step is spent on HALT, VRAM DMA, etc.
+    #[inline]
+    pub fn fetched(&self) -> bool {
+        self.fetched
+    }
+
+    /// Trace every instruction into `sink` before it gets executed.
+    /// Pass `None` to disable tracing. Return the previous sink.
+    pub fn replace_trace_sink(
+        &mut self,
+        sink: Option<Box<dyn TraceSink>>,
+        mode: TraceMode,
+    ) -> Option<Box<dyn TraceSink>> {
+        let tracer = sink.map(|sink| Tracer { sink, mode });
+        std::mem::replace(&mut self.tracer, tracer).map(|tracer| tracer.sink)
+    }
+
+    /// Take the interrupt serviced in last step.
+    #[inline]
+    pub fn take_serviced_interrupt(&mut self) -> Option<InterruptType> {
+        self.serviced_itr.take()
+    }
+
+    #[inline]
+    pub fn bus(&self) -> &BUS {
+        &self.bus
+    }
+
+    #[inline]
+    pub fn bus_mut(&mut self) -> &mut BUS {
+        &mut self.bus
+    }
+
     #[inline]
     fn flag_z(&self) -> bool {
         is_bit_set!(self.reg_f, 7)
@@ -310,6 +532,7 @@ where
     pub fn take_clocks(&mut self) -> u8 {
         let clocks = self.clocks;
         self.clocks = 0;
+        self.elapsed_clocks += clocks as u64;
 
         clocks
     }
@@ -362,6 +585,7 @@ where
             self.jp(interrupt_source.handler_address);
             self.adv_clocks(4); // M3
             self.bus.write(0xFF0F, interrupt_flag & (!interrupt_source.flag));
+            self.serviced_itr = Some(interrupt_source.interrupt_type);
             self.halted = false;
             // Interrupt handler can let CPU continue to handle
             // interrupts via RETI instruction.
@@ -375,10 +599,74 @@ where
     }
 
     pub fn step(&mut self) {
+        self.step_inner();
+
+        if self.tracer.is_some() && self.fetched {
+            self.trace();
+        }
+    }
+
+    #[cold]
+    fn trace(&mut self) {
+        let Some(tracer) = self.tracer.as_mut() else {
+            return;
+        };
+
+        let pc = self.pc.wrapping_sub(1);
+        // Peek so that tracing doesn't hit watchpoints.
+        let pcmem = [0, 1, 2, 3].map(|offset| self.bus.peek(pc.wrapping_add(offset)));
+        let rich = match tracer.mode {
+            TraceMode::Doctor => None,
+            TraceMode::Rich => Some(RichTrace {
+                rom_bank: self.bus.rom_bank(pc),
+                clocks: self.elapsed_clocks + self.clocks as u64,
+                instruction: disasm::decode_raw([pcmem[0], pcmem[1], pcmem[2]]),
+            }),
+        };
+        let record = TraceRecord {
+            a: self.reg_a,
+            f: self.reg_f,
+            b: self.reg_b,
+            c: self.reg_c,
+            d: self.reg_d,
+            e: self.reg_e,
+            h: self.reg_h,
+            l: self.reg_l,
+            sp: self.sp,
+            pc,
+            pcmem,
+            rich,
+        };
+
+        tracer.sink.trace(&record);
+    }
+
+    #[inline(always)]
+    fn step_inner(&mut self) {
+        self.fetched = false;
+
+        if self.speed_switch_cycles > 0 {
+            self.speed_switch_cycles -= 1;
+            self.adv_clocks(4);
+            return;
+        }
+
+        if self.stopped {
+            // Only the time passes, the bus is not stepped.
+            if self.bus.read(0xFF00) & 0x0F != 0x0F {
+                self.stopped = false;
+            }
+            self.clocks = self.clocks.wrapping_add(4);
+            return;
+        }
+
         if self.bus.vdma_active() {
-            // TODO: In normal speed, transfer 2 bytes in 1 M-cycle. In double speed, transfer 2 bytes in 2 M-cycles. Thus, call `step_vdma` once on double speed mode.
-            self.bus.step_vdma();
+            // In normal speed, transfer 2 bytes in 1 M-cycle. In double speed,
+            // transfer 2 bytes in 2 M-cycles, as VRAM DMA keeps real-time rate.
             self.bus.step_vdma();
+            if !self.double_speed {
+                self.bus.step_vdma();
+            }
             self.adv_clocks(4);
             return;
         }
@@ -386,8 +674,13 @@ where
         if self.halted {
             if self.itr_pending() {
                 self.halted = false;
+                self.adv_clocks(4);
+            } else {
+                // Skip machine cycles until any interrupt may be requested,
+                // as long as clocks fit in u8.
+                let m_cycles = self.bus.idle_cycles().clamp(1, 63) as u8;
+                self.adv_clocks(m_cycles * 4);
             }
-            self.adv_clocks(4);
 
             let handle_itr = self.handle_itr;
             self.handle_itr = true;
@@ -395,6 +688,7 @@ where
             if self.ime && handle_itr {
                 if let Some(handler_opcode) = self.handle_interrupts() {
                     self.ir = handler_opcode;
+                    self.fetched = true;
                     return;
                 }
             }
@@ -406,6 +700,7 @@ where
             self.enabling_ime = false;
             if let Some(handler_opcode) = self.handle_interrupts() {
                 self.ir = handler_opcode;
+                self.fetched = true;
                 return;
             }
         }
@@ -436,6 +731,7 @@ where
             }
             0x03 => {
                 // INC BC
+                self.bus.idu_access(self.bc());
                 self.set_bc(inst::inc_16(self.bc()));
 
                 self.adv_clocks(4);
@@ -481,6 +777,7 @@ where
             }
             0x0B => {
                 // DEC BC
+                self.bus.idu_access(self.bc());
                 self.set_bc(inst::dec_16(self.bc()));
 
                 self.adv_clocks(4);
@@ -522,6 +819,7 @@ where
             }
             0x13 => {
                 // INC DE
+                self.bus.idu_access(self.de());
                 self.set_de(inst::inc_16(self.de()));
                 self.adv_clocks(4);
             }
@@ -566,6 +864,7 @@ where
             }
             0x1B => {
                 // DEC DE
+                self.bus.idu_access(self.de());
                 self.set_de(inst::dec_16(self.de()));
                 self.adv_clocks(4);
             }
@@ -611,6 +910,7 @@ where
             }
             0x23 => {
                 // INC HL
+                self.bus.idu_access(self.hl());
                 self.set_hl(inst::inc_16(self.hl()));
                 self.adv_clocks(4);
             }
@@ -659,6 +959,7 @@ where
             }
             0x2B => {
                 // DEC HL
+                self.bus.idu_access(self.hl());
                 self.set_hl(inst::dec_16(self.hl()));
                 self.adv_clocks(4);
             }
@@ -702,6 +1003,7 @@ where
             }
             0x33 => {
                 // INC SP
+                self.bus.idu_access(self.sp);
                 self.sp = inst::inc_16(self.sp);
                 self.adv_clocks(4);
             }
@@ -748,6 +1050,7 @@ where
             }
             0x3B => {
                 // DEC SP
+                self.bus.idu_access(self.sp);
                 self.sp = inst::dec_16(self.sp);
                 self.adv_clocks(4);
             }
@@ -1201,11 +1504,12 @@ where
         }
 
         if halt_bug {
-            self.ir = self.bus.read(self.pc);
+            self.ir = self.bus.cpu_read(self.pc);
             self.adv_clocks(4);
         } else {
             self.ir = self.read_pc();
         }
+        self.fetched = true;
     }
 }
 
@@ -1239,10 +1543,52 @@ mock! {
         fn step_vdma(&mut self) {
             // Noop
         }
+
+        fn set_double_speed(&mut self, double_speed: bool) {
+            // Noop
+        }
+    }
+}
+
+/// Flat 64 KiB memory without any side effects.
+#[cfg(test)]
+pub(crate) struct RamBus {
+    pub(crate) ram: Vec<u8>,
+}
+
+#[cfg(test)]
+impl RamBus {
+    pub(crate) fn new() -> Self {
+        Self { ram: vec![0; 0x10000] }
     }
 }
 
 #[cfg(test)]
+impl Memory for RamBus {
+    fn write(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+}
+
+#[cfg(test)]
+impl gb_shared::Bus for RamBus {
+    fn step(&mut self, _clocks: u8) {}
+
+    fn vdma_active(&self) -> bool {
+        false
+    }
+
+    fn step_vdma(&mut self) {}
+
+    fn set_double_speed(&mut self, _double_speed: bool) {}
+}
+
+#[cfg(test)]
+#[allow(clippy::type_complexity)]
 mod tests {
     use super::*;
     use mockall::predicate::*;
@@ -1292,6 +1638,39 @@ mod tests {
             assert_eq!(cpu.sp, 0xFFFE);
             assert_eq!(cpu.pc, 0x0100);
         }
+
+        #[test]
+        fn post_boot_registers() {
+            let cases = [
+                (HardwareModel::DMG0, [0x0100, 0xFF13, 0x00C1, 0x8403]),
+                (HardwareModel::DMG, [0x01B0, 0x0013, 0x00D8, 0x014D]),
+                (HardwareModel::MGB, [0xFFB0, 0x0013, 0x00D8, 0x014D]),
+                (HardwareModel::SGB, [0x0100, 0x0014, 0x0000, 0xC060]),
+                (HardwareModel::SGB2, [0xFF00, 0x0014, 0x0000, 0xC060]),
+                (HardwareModel::CGB0, [0x1180, 0x0000, 0x0008, 0x007C]),
+                (HardwareModel::CGBE, [0x1180, 0x0000, 0x0008, 0x007C]),
+                (HardwareModel::AGB, [0x1100, 0x0100, 0x0008, 0x007C]),
+            ];
+            for (model, regs) in cases {
+                let cpu = Cpu::new_post_boot(MockBus::new(), model, MachineModel::DMG, 0x12);
+                assert_eq!([cpu.af(), cpu.bc(), cpu.de(), cpu.hl()], regs, "{:?}", model);
+            }
+
+            // Flags on DMG depend on the header checksum.
+            let cpu = Cpu::new_post_boot(MockBus::new(), HardwareModel::DMG, MachineModel::DMG, 0);
+            assert_eq!(cpu.af(), 0x0180);
+        }
+
+        #[test]
+        fn post_boot_registers_in_cgb_mode() {
+            for (model, regs) in [
+                (HardwareModel::CGBE, [0x1180, 0x0000, 0xFF56, 0x000D]),
+                (HardwareModel::AGB, [0x1100, 0x0100, 0xFF56, 0x000D]),
+            ] {
+                let cpu = Cpu::new_post_boot(MockBus::new(), model, MachineModel::CGB, 0x12);
+                assert_eq!([cpu.af(), cpu.bc(), cpu.de(), cpu.hl()], regs, "{:?}", model);
+            }
+        }
     }
 
     mod stack {
@@ -1502,6 +1881,150 @@ mod tests {
             assert_eq!(cpu.pc, 0x1235);
         }
     }
+
+    mod speed_switch {
+        use super::*;
+
+        #[test]
+        fn switch_speed_on_stop_if_armed() {
+            let mut mock = prepare_bus();
+            mock.expect_read().with(eq(0xFF00)).return_const(0xCF);
+            mock.expect_read().with(eq(0xFF0F)).return_const(0);
+            mock.expect_read().with(eq(0xFFFF)).return_const(0);
+            mock.expect_read().with(eq(0xFF4D)).once().return_const(0x7F);
+            mock.expect_set_double_speed().with(eq(true)).once().return_const(());
+            mock.expect_write().with(eq(0xFF04), eq(0)).once().return_const(());
+
+            let mut cpu = Cpu::new_cgb(mock);
+            cpu.stop();
+
+            assert!(cpu.double_speed);
+            assert!(!cpu.stopped);
+            assert_eq!(cpu.speed_switch_cycles, SPEED_SWITCH_CYCLES);
+        }
+
+        #[test]
+        fn stop_if_not_armed() {
+            let mut mock = prepare_bus();
+            mock.expect_read().with(eq(0xFF00)).return_const(0xCF);
+            mock.expect_read().with(eq(0xFF0F)).return_const(0);
+            mock.expect_read().with(eq(0xFFFF)).return_const(0);
+            mock.expect_read().with(eq(0xFF4D)).once().return_const(0x7E);
+            mock.expect_write().with(eq(0xFF04), eq(0)).once().return_const(());
+            mock.expect_set_double_speed().never();
+
+            let mut cpu = Cpu::new_cgb(mock);
+            cpu.stop();
+
+            assert!(!cpu.double_speed);
+            assert!(cpu.stopped);
+        }
+
+        #[test]
+        fn ignore_key1_on_dmg() {
+            let mut mock = prepare_bus();
+            mock.expect_read().with(eq(0xFF00)).return_const(0xCF);
+            mock.expect_read().with(eq(0xFF0F)).return_const(0);
+            mock.expect_read().with(eq(0xFFFF)).return_const(0);
+            mock.expect_read().with(eq(0xFF4D)).never();
+            mock.expect_write().with(eq(0xFF04), eq(0)).once().return_const(());
+            mock.expect_set_double_speed().never();
+
+            let mut cpu = Cpu::new_dmg(mock, 0);
+            cpu.stop();
+
+            assert!(!cpu.double_speed);
+            assert!(cpu.stopped);
+        }
+
+        #[test]
+        fn count_real_time_clocks_in_double_speed() {
+            let mut cpu = Cpu::new_cgb(prepare_bus());
+            cpu.double_speed = true;
+            cpu.speed_switch_cycles = 2;
+
+            cpu.step();
+            cpu.step();
+
+            assert_eq!(cpu.take_clocks(), 4);
+            assert_eq!(cpu.speed_switch_cycles, 0);
+        }
+    }
+
+    mod stop {
+        use super::*;
+
+        const PC: u16 = 0xC000;
+
+        /// STOP; 0x00; INC A
+        fn prepare(joypad: u8, itr_pending: bool) -> Cpu<RamBus> {
+            let mut bus = RamBus::new();
+            bus.ram[PC as usize..PC as usize + 3].copy_from_slice(&[0x10, 0x00, 0x3C]);
+            bus.ram[0xFF00] = joypad;
+            bus.ram[0xFF04] = 0x12;
+            if itr_pending {
+                bus.ram[0xFF0F] = 0x01;
+                bus.ram[0xFFFF] = 0x01;
+            }
+
+            let mut cpu = Cpu::new(bus);
+            cpu.set_pc(PC);
+            cpu
+        }
+
+        #[test]
+        fn wake_up_by_joypad() {
+            // Direction buttons selected, none pressed.
+            let mut cpu = prepare(0xEF, false);
+            cpu.step();
+            assert!(cpu.stopped);
+            assert_eq!(cpu.bus.ram[0xFF04], 0);
+            // STOP is 2-byte.
+            assert_eq!(cpu.pc(), PC + 2);
+
+            for _ in 0..10 {
+                cpu.step();
+            }
+            assert!(cpu.stopped);
+            assert_eq!(cpu.reg_a, 0x01);
+
+            cpu.bus.ram[0xFF00] = 0xEE;
+            cpu.step();
+            assert!(!cpu.stopped);
+            cpu.step();
+            assert_eq!(cpu.reg_a, 0x02);
+        }
+
+        #[test]
+        fn stop_with_interrupt_pending() {
+            let mut cpu = prepare(0xEF, true);
+            cpu.step();
+            assert!(cpu.stopped);
+            assert_eq!(cpu.bus.ram[0xFF04], 0);
+            // STOP is 1-byte.
+            assert_eq!(cpu.pc(), PC + 1);
+        }
+
+        #[test]
+        fn halt_if_button_held() {
+            let mut cpu = prepare(0xEE, false);
+            cpu.step();
+            assert!(!cpu.stopped);
+            assert!(cpu.halted);
+            assert_eq!(cpu.bus.ram[0xFF04], 0x12);
+            assert_eq!(cpu.pc(), PC + 2);
+        }
+
+        #[test]
+        fn nop_if_button_held_with_interrupt_pending() {
+            let mut cpu = prepare(0xEE, true);
+            cpu.step();
+            assert!(!cpu.stopped);
+            assert!(!cpu.halted);
+            assert_eq!(cpu.bus.ram[0xFF04], 0x12);
+            assert_eq!(cpu.pc(), PC + 1);
+        }
+    }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -1520,6 +2043,8 @@ pub struct CpuSnapshot {
     enabling_ime: bool,
     halted: bool,
     stopped: bool,
+    double_speed: bool,
+    speed_switch_cycles: u16,
     clocks: u8,
     ir: u8,
     handle_itr: bool,
@@ -1547,6 +2072,8 @@ where
             enabling_ime: self.enabling_ime,
             halted: self.halted,
             stopped: self.stopped,
+            double_speed: self.double_speed,
+            speed_switch_cycles: self.speed_switch_cycles,
             clocks: self.clocks,
             ir: self.ir,
             handle_itr: self.handle_itr,
@@ -1568,6 +2095,8 @@ where
         self.enabling_ime = snapshot.enabling_ime;
         self.halted = snapshot.halted;
         self.stopped = snapshot.stopped;
+        self.double_speed = snapshot.double_speed;
+        self.speed_switch_cycles = snapshot.speed_switch_cycles;
         self.clocks = snapshot.clocks;
         self.ir = snapshot.ir;
         self.handle_itr = snapshot.handle_itr;
@@ -0,0 +1,255 @@
+//! Per-instruction trace, compatible with [Gameboy Doctor](https://github.com/robert/gameboy-doctor).
+
+use crate::disasm::Instruction;
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceMode {
+    /// Gameboy Doctor log format only.
+    #[default]
+    Doctor,
+    /// Doctor format followed by ROM bank, cycle counter and mnemonic.
+    Rich,
+}
+
+/// Extra fields of [`TraceMode::Rich`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RichTrace {
+    /// ROM bank mapped at PC.
+    pub rom_bank: usize,
+    /// Real-time clocks elapsed before the instruction.
+    pub clocks: u64,
+    pub instruction: Instruction,
+}
+
+/// CPU state right before the instruction at `pc` gets executed.
+///
+/// Display as `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`,
+/// followed by `BANK:01 CYC:1234 JP $0213` in rich mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// 4 bytes at PC.
+    pub pcmem: [u8; 4],
+    pub rich: Option<RichTrace>,
+}
+
+impl core::fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            self.a,
+            self.f,
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.h,
+            self.l,
+            self.sp,
+            self.pc,
+            self.pcmem[0],
+            self.pcmem[1],
+            self.pcmem[2],
+            self.pcmem[3],
+        )?;
+
+        if let Some(rich) = &self.rich {
+            write!(f, " BANK:{:02X} CYC:{} {}", rich.rom_bank, rich.clocks, rich.instruction)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub trait TraceSink: Send {
+    fn trace(&mut self, record: &TraceRecord);
+}
+
+impl<F> TraceSink for F
+where
+    F: FnMut(&TraceRecord) + Send,
+{
+    fn trace(&mut self, record: &TraceRecord) {
+        self(record)
+    }
+}
+
+/// Write a line per instruction, e.g. into a file. Wrap the writer
+/// with [`std::io::BufWriter`] for better performance.
+pub struct WriteSink<W: Write> {
+    writer: W,
+    failed: bool,
+}
+
+impl<W: Write> WriteSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, failed: false }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> TraceSink for WriteSink<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        if self.failed {
+            return;
+        }
+
+        if let Err(err) = writeln!(self.writer, "{}", record) {
+            log::error!("Failed to write trace, tracing stopped: {}", err);
+            self.failed = true;
+        }
+    }
+}
+
+/// Keep the latest `capacity` records. Clones share the same buffer,
+/// so one can be handed to the CPU while the other is used to read records.
+#[derive(Debug, Clone)]
+pub struct RingBuffer {
+    records: Arc<Mutex<VecDeque<TraceRecord>>>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self { records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))), capacity }
+    }
+
+    /// Records from oldest to latest.
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+}
+
+impl TraceSink for RingBuffer {
+    fn trace(&mut self, record: &TraceRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record.clone());
+    }
+}
+
+pub(crate) struct Tracer {
+    pub(crate) sink: Box<dyn TraceSink>,
+    pub(crate) mode: TraceMode,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disasm::decode_bytes, Cpu, RamBus};
+
+    fn record(pc: u16) -> TraceRecord {
+        TraceRecord {
+            a: 0x01,
+            f: 0xB0,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xD8,
+            h: 0x01,
+            l: 0x4D,
+            sp: 0xFFFE,
+            pc,
+            pcmem: [0x00, 0xC3, 0x13, 0x02],
+            rich: None,
+        }
+    }
+
+    #[test]
+    fn doctor_format() {
+        let mut record = record(0x0100);
+        assert_eq!(
+            record.to_string(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+        );
+
+        record.rich = Some(RichTrace {
+            rom_bank: 1,
+            clocks: 1234,
+            instruction: decode_bytes(&[0xC3, 0x13, 0x02]).unwrap(),
+        });
+        assert!(record.to_string().ends_with("PCMEM:00,C3,13,02 BANK:01 CYC:1234 JP $0213"));
+    }
+
+    #[test]
+    fn ring_buffer_keeps_latest() {
+        let buffer = RingBuffer::new(2);
+        let mut sink = buffer.clone();
+        for pc in 0..3 {
+            sink.trace(&record(pc));
+        }
+
+        let pcs = buffer.records().iter().map(|r| r.pc).collect::<Vec<_>>();
+        assert_eq!(pcs, vec![1, 2]);
+    }
+
+    #[test]
+    fn write_sink() {
+        let mut sink = WriteSink::new(Vec::new());
+        sink.trace(&record(0x0100));
+        sink.trace(&record(0x0101));
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(output.lines().count(), 2);
+        assert!(output.lines().nth(1).unwrap().contains("PC:0101"));
+    }
+
+    #[test]
+    fn trace_instruction_boundaries() {
+        let mut bus = RamBus::new();
+        // LD A,$42; JP $0100
+        bus.ram[0x0100..0x0105].copy_from_slice(&[0x3E, 0x42, 0xC3, 0x00, 0x01]);
+
+        let buffer = RingBuffer::new(16);
+        let mut cpu = Cpu::new(bus);
+        cpu.replace_trace_sink(Some(Box::new(buffer.clone())), TraceMode::Rich);
+        for _ in 0..4 {
+            cpu.step();
+            cpu.take_clocks();
+        }
+
+        let lines = buffer.records().iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,C3,00 BANK:00 CYC:4 LD A,$42",
+                "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:C3,00,01,00 BANK:00 CYC:12 JP $0100",
+                "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,C3,00 BANK:00 CYC:28 LD A,$42",
+                "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:C3,00,01,00 BANK:00 CYC:36 JP $0100",
+            ]
+        );
+
+        let sink = cpu.replace_trace_sink(None, TraceMode::Doctor);
+        assert!(sink.is_some());
+        cpu.step();
+        assert_eq!(buffer.records().len(), 4);
+    }
+}
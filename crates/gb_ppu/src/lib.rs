@@ -18,12 +18,18 @@ use vram::{BackgroundAttrs, VideoRam, VideoRamSnapshot};
 
 pub type VideoFrame = [u8; 69120]; // 160 * 144 * 3
 
-#[cfg(feature = "debug_frame")]
+// Handles are `Send` so that the emulator can be moved across threads,
+// except on wasm, where they usually capture JS values.
+#[cfg(all(feature = "debug_frame", not(target_family = "wasm")))]
+pub type FrameHandle = dyn FnMut(&VideoFrame, &[u8]) + Send;
+#[cfg(all(not(feature = "debug_frame"), not(target_family = "wasm")))]
+pub type FrameHandle = dyn FnMut(&VideoFrame) + Send;
+#[cfg(all(feature = "debug_frame", target_family = "wasm"))]
 pub type FrameHandle = dyn FnMut(&VideoFrame, &[u8]);
-#[cfg(not(feature = "debug_frame"))]
+#[cfg(all(not(feature = "debug_frame"), target_family = "wasm"))]
 pub type FrameHandle = dyn FnMut(&VideoFrame);
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub(crate) struct PpuWorkState {
     /// X of current scanline.
     /// Reset when moving to next scanline.
@@ -70,6 +76,13 @@ pub struct Ppu {
     pub frame_handle: Option<Box<FrameHandle>>,
     coerce_bw: Option<bool>,
     monochrome_palette_id: Option<u16>,
+    /// CGB running a DMG cartridge, set up by the boot ROM.
+    dmg_compatibility: bool,
+    /// OPRI, object priority mode(CGB only).
+    /// - Bit 0: 0 for by location in OAM, 1 for by X coordinate.
+    ///
+    /// @see https://gbdev.io/pandocs/CGB_Registers.html#ff6c--opri-cgb-mode-only-object-priority-mode
+    opri: u8,
 }
 
 impl Default for Ppu {
@@ -91,12 +104,37 @@ impl Default for Ppu {
             palette: Palette::new(machine_model.into(), None),
             coerce_bw: None,
             monochrome_palette_id: None,
+            dmg_compatibility: false,
+            opri: 0x01,
             #[cfg(feature = "debug_frame")]
             dbg_video_buffer: vec![0xFF; (256 * 256 * 3 * 2) + (3 * 12)],
         }
     }
 }
 
+/// The frame handle is not cloned.
+impl Clone for Ppu {
+    fn clone(&self) -> Self {
+        Self {
+            vram: self.vram.clone(),
+            oam: self.oam,
+            lcd: self.lcd,
+            palette: self.palette.clone(),
+            work_state: self.work_state.clone(),
+            video_buffer: self.video_buffer.clone(),
+            #[cfg(feature = "debug_frame")]
+            dbg_video_buffer: self.dbg_video_buffer.clone(),
+            irq: self.irq.clone(),
+            machine_model: self.machine_model,
+            frame_handle: None,
+            coerce_bw: self.coerce_bw,
+            monochrome_palette_id: self.monochrome_palette_id,
+            dmg_compatibility: self.dmg_compatibility,
+            opri: self.opri,
+        }
+    }
+}
+
 impl Ppu {
     pub fn new(machine_model: MachineModel, monochrome_palette_id: Option<u16>) -> Self {
         Self {
@@ -109,6 +147,7 @@ impl Ppu {
             vram: VideoRam::new(machine_model),
             machine_model,
             monochrome_palette_id,
+            opri: if machine_model == MachineModel::CGB { 0x00 } else { 0x01 },
             ..Self::default()
         }
     }
@@ -117,6 +156,56 @@ impl Ppu {
         self.irq.take()
     }
 
+    /// Power-on state before the boot ROM runs, where LCD is off.
+    pub fn power_on(&mut self) {
+        self.lcd.lcdc = 0;
+        self.power(false);
+    }
+
+    /// Render as DMG with the palettes set up by CGB boot ROM.
+    pub fn enter_dmg_compatibility_mode(&mut self) {
+        if self.machine_model != MachineModel::CGB {
+            return;
+        }
+
+        self.machine_model = MachineModel::DMG;
+        self.dmg_compatibility = true;
+        self.opri = 0x01;
+        self.palette.enter_dmg_compatibility_mode();
+    }
+
+    /// Load the compatibility palette into CGB palette RAM as the CGB
+    /// boot ROM does for DMG cartridges, before entering DMG compatibility mode.
+    pub fn load_compatibility_palette(&mut self, palette_id: Option<u16>) {
+        self.palette.load_compatibility_palette(palette_id);
+    }
+
+    /// OAM corruption bug on DMG, triggered by writing to or putting an
+    /// address in [0xFE00, 0xFEFF] on the bus during mode 2.
+    ///
+    /// OAM is split into 20 rows of 8 bytes, and the row being scanned
+    /// gets corrupted with the preceding one.
+    /// @see https://gbdev.io/pandocs/OAM_Corruption_Bug.html#write-corruption
+    pub fn corrupt_oam(&mut self) {
+        if !self.lcd.lcd_enabled() || self.lcd_mode() != LCDMode::OamScan {
+            return;
+        }
+
+        // A row is scanned every machine cycle.
+        let row = self.work_state.scanline_dots as usize / 4;
+        if row == 0 || row >= 20 {
+            return;
+        }
+
+        let (preceding, current) = self.oam.split_at_mut(row * 8);
+        let preceding = &preceding[(row - 1) * 8..];
+        let word =
+            |bytes: &[u8], nth: usize| u16::from_le_bytes([bytes[nth * 2], bytes[nth * 2 + 1]]);
+        let (a, b, c) = (word(current, 0), word(preceding, 0), word(preceding, 2));
+        current[..2].copy_from_slice(&(((a ^ c) & (b ^ c)) ^ c).to_le_bytes());
+        current[2..8].copy_from_slice(&preceding[2..8]);
+    }
+
     pub fn ly(&self) -> u8 {
         self.lcd.ly
     }
@@ -125,6 +214,30 @@ impl Ppu {
         LCDMode::from(self.lcd.stat)
     }
 
+    /// OAM is inaccessible to CPU during mode 2 and 3.
+    #[inline]
+    pub fn oam_accessible(&self) -> bool {
+        matches!(self.lcd_mode(), LCDMode::HBlank | LCDMode::VBlank)
+    }
+
+    /// VRAM of all banks, which the bus can access directly at
+    /// [`Ppu::vram_bank_offset`].
+    #[inline]
+    pub fn vram(&self) -> &[u8] {
+        self.vram.ram()
+    }
+
+    #[inline]
+    pub fn vram_mut(&mut self) -> &mut [u8] {
+        self.vram.ram_mut()
+    }
+
+    /// Offset in [`Ppu::vram`] of the bank mapped at [0x8000, 0x9FFF].
+    #[inline]
+    pub fn vram_bank_offset(&self) -> usize {
+        self.vram.bank_offset()
+    }
+
     fn set_lcd_mode(&mut self, mode: LCDMode) {
         // Unset bit 0 and bit 1
         let mut stat = self.lcd.stat;
@@ -258,6 +371,44 @@ impl Ppu {
         }
     }
 
+    /// Dots from now in which no interrupt can be requested, i.e. before
+    /// the next mode or scanline change, or the mode 2 stat interrupt.
+    pub fn idle_dots(&self) -> u32 {
+        if !self.lcd.lcd_enabled() {
+            return u32::MAX;
+        }
+
+        let dots = self.work_state.scanline_dots as u32;
+        let next = match self.lcd_mode() {
+            LCDMode::OamScan if dots == 0 => 1,
+            LCDMode::OamScan => 80,
+            LCDMode::RenderPixel => {
+                dots.max(80 + 12) + (RESOLUTION_X as u32 - self.work_state.scanline_x as u32)
+            }
+            LCDMode::HBlank | LCDMode::VBlank => DOTS_PER_SCANLINE as u32,
+        };
+
+        next.saturating_sub(dots + 1)
+    }
+
+    /// Run `dots` which are idle, see [`Ppu::idle_dots`].
+    pub fn step_idle(&mut self, dots: u32) {
+        debug_assert!(dots <= self.idle_dots());
+        if !self.lcd.lcd_enabled() {
+            return;
+        }
+
+        match self.lcd_mode() {
+            // Nothing but counting dots.
+            LCDMode::HBlank | LCDMode::VBlank => self.work_state.scanline_dots += dots as u16,
+            LCDMode::OamScan | LCDMode::RenderPixel => {
+                for _ in 0..dots {
+                    self.step();
+                }
+            }
+        }
+    }
+
     pub fn step(&mut self) {
         if !self.lcd.lcd_enabled() {
             return;
@@ -308,7 +459,7 @@ impl Ppu {
             // For CGB, the priority is determined by the location in OAM.
             // The earlier the object, the higher its priority.
             //
-            if self.machine_model == MachineModel::DMG {
+            if self.machine_model == MachineModel::DMG || is_bit_set!(self.opri, 0) {
                 // It's notable that `sort_by` is stable.
                 self.work_state.scanline_objects.sort_by(|a, b| a.x.cmp(&b.x));
             }
@@ -564,9 +715,7 @@ impl Memory for Ppu {
             0xFF4B => self.lcd.wx = value,
             0xFF4F => self.vram.write(addr, value),
             0xFF68..=0xFF6B => self.palette.write(addr, value),
-            0xFF6C => {
-                log::warn!("OPRI should not be written by program.");
-            }
+            0xFF6C => self.opri = value & 0x01,
             _ => unreachable!("Invalid PPU address: {:#X}", addr),
         }
     }
@@ -586,7 +735,7 @@ impl Memory for Ppu {
             0xFF4B => self.lcd.wx,
             0xFF4F => self.vram.read(addr),
             0xFF68..=0xFF6B => self.palette.read(addr),
-            0xFF6C => 0xFE | if self.machine_model == MachineModel::CGB { 0x00 } else { 0x01 },
+            0xFF6C => 0xFE | self.opri,
             _ => unreachable!("Invalid PPU address: {:#X}", addr),
         }
     }
@@ -606,6 +755,8 @@ pub struct PpuSnapshot {
     window_used: bool,
     //#endregion
     irq: u8,
+    dmg_compatibility: bool,
+    opri: u8,
 }
 
 impl Snapshot for Ppu {
@@ -628,6 +779,8 @@ impl Snapshot for Ppu {
             window_line: self.work_state.window_line,
             window_used: self.work_state.window_used,
             irq: self.irq.0,
+            dmg_compatibility: self.dmg_compatibility,
+            opri: self.opri,
         }
     }
 
@@ -650,6 +803,10 @@ impl Snapshot for Ppu {
         self.work_state.window_line = snapshot.window_line;
         self.work_state.window_used = snapshot.window_used;
         self.irq.0 = snapshot.irq;
+        self.opri = snapshot.opri;
+        if snapshot.dmg_compatibility {
+            self.enter_dmg_compatibility_mode();
+        }
     }
 }
 
@@ -666,6 +823,65 @@ mod tests {
         assert_eq!(ppu.read(0xFF41), 0b1000_0101);
     }
 
+    #[test]
+    fn dmg_compatibility_palettes() {
+        let mut ppu = Ppu::new(MachineModel::CGB, None);
+        // BG0 color 1, OBJ1 color 3
+        for (cps_addr, cps, color) in [(0xFF68, 0x82, 0x001F), (0xFF6A, 0x8E, 0x7C00)] {
+            ppu.write(cps_addr, cps);
+            let [lo, hi] = u16::to_le_bytes(color);
+            ppu.write(cps_addr + 1, lo);
+            ppu.write(cps_addr + 1, hi);
+        }
+
+        ppu.enter_dmg_compatibility_mode();
+        ppu.write(0xFF47, 0b01);
+        ppu.write(0xFF49, 0b11);
+        assert_eq!(ppu.palette.background_color(0, 0), 0xFF0000);
+        assert_eq!(ppu.palette.object_color(1, 0), 0x0000FF);
+
+        // Palettes are locked.
+        ppu.write(0xFF68, 0x80);
+        assert_eq!(ppu.read(0xFF69), 0xFF);
+        // Objects are prioritized by X coordinate.
+        assert_eq!(ppu.read(0xFF6C), 0xFF);
+    }
+
+    #[test]
+    fn load_compatibility_palette() {
+        let mut ppu = Ppu::new(MachineModel::CGB, None);
+        ppu.load_compatibility_palette(None);
+        ppu.enter_dmg_compatibility_mode();
+        ppu.write(0xFF47, 0b01);
+        ppu.write(0xFF48, 0b11);
+        // Precision is lost in RGB555.
+        assert_eq!(ppu.palette.background_color(0, 0), 0xADADAD);
+        assert_eq!(ppu.palette.object_color(0, 0), 0x000000);
+    }
+
+    #[test]
+    fn oam_corruption() {
+        let mut ppu = Ppu::new(MachineModel::DMG, None);
+        for (i, byte) in ppu.oam.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        ppu.oam[8..16].copy_from_slice(&[0x78, 0x56, 0xF0, 0xDE, 0xBC, 0x9A, 0x44, 0x55]);
+        ppu.oam[16..18].copy_from_slice(&[0x34, 0x12]);
+        ppu.write(0xFF40, 0x80);
+        for _ in 0..8 {
+            ppu.step();
+        }
+
+        ppu.corrupt_oam();
+        // a: 0x1234, the first word of the row being scanned.
+        // b: 0x5678, the first word of the preceding row.
+        // c: 0x9ABC, the third word of the preceding row.
+        // ((a ^ c) & (b ^ c)) ^ c is 0x123C, or 0x5670 if c were the
+        // second word 0xDEF0.
+        assert_eq!(ppu.oam[16..24], [0x3C, 0x12, 0xF0, 0xDE, 0xBC, 0x9A, 0x44, 0x55]);
+        assert_eq!(ppu.oam[24..32], [24, 25, 26, 27, 28, 29, 30, 31]);
+    }
+
     #[test]
     fn read_only_ly() {
         let mut ppu = Ppu::default();
@@ -21,6 +21,7 @@ impl From<MachineModel> for ColorSpace {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct Palette {
     color_space: ColorSpace,
     bgp: u8,
@@ -89,6 +90,32 @@ impl Palette {
         }
     }
 
+    /// Write `palette_id`'s colors into BG0, OBJ0 and OBJ1 of palette RAM.
+    pub(crate) fn load_compatibility_palette(&mut self, palette_id: Option<u16>) {
+        let colors = palette_id.and_then(find_palette).unwrap_or_else(|| {
+            let mut colors = [0; 12];
+            colors.chunks_mut(4).for_each(|chunk| chunk.copy_from_slice(FALLBACK_COLORS));
+            colors
+        });
+
+        for (nth, color) in colors.into_iter().enumerate() {
+            let (bg, addr) = if nth < 4 { (true, nth * 2) } else { (false, (nth - 4) * 2) };
+            let [r, g, b] = [color >> 16, color >> 8, color].map(|c| (c & 0xFF) as u16 >> 3);
+            let [lo, hi] = (r | (g << 5) | (b << 10)).to_le_bytes();
+            self.update_color(bg, addr as u8, lo);
+            self.update_color(bg, addr as u8 + 1, hi);
+        }
+    }
+
+    /// In DMG compatibility mode, BGP, OBP0 and OBP1 index into CGB
+    /// palettes BG0, OBJ0 and OBJ1, which are set up by the boot ROM.
+    /// @see https://gbdev.io/pandocs/Power_Up_Sequence.html#compatibility-palettes
+    pub(crate) fn enter_dmg_compatibility_mode(&mut self) {
+        self.colors[1] = self.colors[8];
+        self.colors[2] = self.colors[9];
+        self.color_space = ColorSpace::Monochrome;
+    }
+
     pub(crate) fn background_color(&self, palette_id: u8, color_id: u8) -> u32 {
         match self.color_space {
             ColorSpace::Monochrome => {
@@ -147,6 +174,8 @@ impl Memory for Palette {
             0xFF69 if is_polychrome => self.bcps = self.update_color(true, self.bcps, value),
             0xFF6A if is_polychrome => self.ocps = value,
             0xFF6B if is_polychrome => self.ocps = self.update_color(false, self.ocps, value),
+            // Locked on DMG, or in DMG compatibility mode.
+            0xFF68..=0xFF6B => {}
             _ => unreachable!("Invalid Palette write at {:#X} {:#X}", addr, value),
         }
     }
@@ -162,6 +191,7 @@ impl Memory for Palette {
             0xFF69 if is_polychrome => self.bcpd[(self.bcps & 0x3F) as usize],
             0xFF6A if is_polychrome => self.ocps,
             0xFF6B if is_polychrome => self.ocpd[(self.ocps & 0x3F) as usize],
+            0xFF68..=0xFF6B => 0xFF,
             _ => unreachable!("Invalid Palette read at {:#X}", addr),
         }
     }
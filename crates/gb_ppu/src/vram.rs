@@ -1,6 +1,6 @@
 use gb_shared::{is_bit_set, MachineModel, Memory, Snapshot};
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub(crate) struct VideoRam {
     /// Tile data area(in size of 0x1800).
     /// There are total 384 tiles, each tile has 16 bytes.
@@ -39,6 +39,21 @@ impl VideoRam {
 }
 
 impl VideoRam {
+    #[inline]
+    pub(crate) fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    #[inline]
+    pub(crate) fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    #[inline]
+    pub(crate) fn bank_offset(&self) -> usize {
+        self.bank_num as usize * 0x2000
+    }
+
     pub(crate) fn tile_data(&self, bank_num: u8, index: usize) -> &[u8; 16] {
         let offset = index * 16 + (bank_num as usize * 0x2000);
 
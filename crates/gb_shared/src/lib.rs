@@ -22,6 +22,35 @@ pub trait Bus: Memory {
     fn step(&mut self, clocks: u8);
     fn vdma_active(&self) -> bool;
     fn step_vdma(&mut self);
+    /// Notify the bus that CPU has switched its speed(CGB only).
+    /// While in double speed mode, the clocks passed to `step` are
+    /// CPU clocks, which are twice as fast as the real-time clocks.
+    ///
+    /// @see https://gbdev.io/pandocs/CGB_Registers.html#ff4d--key1-cgb-mode-only-prepare-speed-switch
+    fn set_double_speed(&mut self, double_speed: bool);
+    /// Called when the IDU puts `addr` on the address bus, without
+    /// an actual memory access, e.g. `INC rr` and `DEC rr`.
+    fn idu_access(&mut self, _addr: u16) {}
+    /// Read `addr` in a memory access of CPU. Unlike [`Memory::read`],
+    /// which can be used to peek, the bus may bring components up to
+    /// date before reading.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+    /// Read `addr` without side effects, e.g. hitting watchpoints and bus
+    /// conflicts of DMA, used by debugging tools.
+    fn peek(&self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+    /// Machine cycles from now in which no interrupt can be requested,
+    /// so that a halted CPU can skip them at once.
+    fn idle_cycles(&self) -> u32 {
+        0
+    }
+    /// ROM bank mapped at `addr`, used by debugging tools only.
+    fn rom_bank(&self, _addr: u16) -> usize {
+        0
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -52,7 +81,7 @@ pub trait InterruptRequest {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Interrupt(pub u8);
 
 impl InterruptRequest for Interrupt {
@@ -91,6 +120,57 @@ pub enum MachineModel {
     CGB,
 }
 
+/// Hardware revision, which decides the post-boot state and some
+/// behavioral differences.
+///
+/// @see https://gbdev.io/pandocs/Power_Up_Sequence.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HardwareModel {
+    /// Early DMG, sold in Japan only.
+    DMG0,
+    /// DMG-CPU A, B and C.
+    DMG,
+    /// Game Boy Pocket and Game Boy Light.
+    MGB,
+    SGB,
+    SGB2,
+    CGB0,
+    CGBA,
+    CGBB,
+    CGBC,
+    CGBD,
+    CGBE,
+    /// Game Boy Advance (SP) running in CGB mode.
+    AGB,
+}
+
+impl HardwareModel {
+    #[inline]
+    pub fn machine_model(self) -> MachineModel {
+        match self {
+            Self::DMG0 | Self::DMG | Self::MGB | Self::SGB | Self::SGB2 => MachineModel::DMG,
+            _ => MachineModel::CGB,
+        }
+    }
+
+    /// Accessing OAM in mode 2 corrupts it on DMG based models.
+    /// @see https://gbdev.io/pandocs/OAM_Corruption_Bug.html
+    #[inline]
+    pub fn has_oam_bug(self) -> bool {
+        self.machine_model() == MachineModel::DMG
+    }
+}
+
+impl From<MachineModel> for HardwareModel {
+    /// The most common revision of the family.
+    fn from(value: MachineModel) -> Self {
+        match value {
+            MachineModel::DMG => Self::DMG,
+            MachineModel::CGB => Self::CGBE,
+        }
+    }
+}
+
 pub trait ByteView {
     /// Most significant byte
     fn msb(self) -> u8;
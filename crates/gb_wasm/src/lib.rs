@@ -137,7 +137,9 @@ impl GameBoyHandle {
         let rom = rom.to_vec();
         let cart = Cartridge::try_from(rom).unwrap();
 
-        let mut gb = GameBoy::new(Manifest { cart, sample_rate });
+        let mut manifest = Manifest::new(cart);
+        manifest.sample_rate = sample_rate;
+        let mut gb = GameBoy::new(manifest);
         if let Some(sav) = sav {
             gb.resume_cartridge(&sav).unwrap();
         }
@@ -313,7 +315,7 @@ pub async fn obtain_metadata(rom: Uint8ClampedArray, frame_at: Option<u32>) -> G
         std::str::from_utf8(&title).unwrap().to_owned()
     };
 
-    let mut gb = GameBoy::new(Manifest { cart, sample_rate: None });
+    let mut gb = GameBoy::new(Manifest::new(cart));
     const SCALE: u32 = 2;
     let canvas = OffscreenCanvas::new(160 * SCALE, 144 * SCALE).unwrap();
     let canvas_context = canvas